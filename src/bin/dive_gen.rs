@@ -0,0 +1,44 @@
+// Writes a generated dive level in the same format as assets/dives.
+//
+//     cargo run --bin dive_gen -- <seed> [bank] [level] [out.txt]
+//
+// Bank and level pick the difficulty the game would use. Without an output
+// path the map is printed.
+
+use std::env;
+use std::process;
+
+use ld52::generator::{self, Difficulty};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        println!("usage: dive_gen <seed> [bank] [level] [out.txt]");
+        process::exit(2);
+    }
+
+    let seed: u64 = args[0].parse().expect("Seed must be a number!");
+    let bank: u32 = args.get(1).map_or(0, |arg| arg.parse().expect("Bank must be a number!"));
+    let level: u32 = args.get(2).map_or(0, |arg| arg.parse().expect("Level must be a number!"));
+
+    let difficulty = Difficulty::for_level(bank, level, level == 2);
+
+    let (map, solution) = match generator::generate(generator::level_seed(seed, bank, level), &difficulty) {
+        Some(generated) => generated,
+        None => {
+            eprintln!("No level found for seed {} within {} attempts", seed, difficulty.attempts);
+            process::exit(1);
+        }
+    };
+
+    match args.get(3) {
+        Some(path) => {
+            map.save(path).expect("Cannot write map!");
+            println!("{}: {} move(s): {}", path, solution.moves.len(), solution.keys());
+        },
+        None => {
+            println!("{}", map.to_text());
+            eprintln!("{} move(s): {}", solution.moves.len(), solution.keys());
+        },
+    }
+}
@@ -0,0 +1,50 @@
+// Checks dive maps against the slide rules used in game.
+//
+//     cargo run --bin dive_solver [map.txt ...]
+//
+// With no arguments every map in assets/dives is checked. Exits with an
+// error code if any map cannot be finished.
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use ld52::dive::{self, DiveMap, Exit};
+
+fn main() {
+    let mut paths: Vec<PathBuf> = env::args().skip(1).map(PathBuf::from).collect();
+    if paths.is_empty() {
+        paths = dive::map_paths(dive::MAP_DIR).expect("Cannot read dive folder!");
+    }
+
+    let mut all_solvable = true;
+
+    for path in paths {
+        let map = match DiveMap::load(&path) {
+            Ok(map) => map,
+            Err(e) => {
+                println!("{}: cannot open ({})", path.display(), e);
+                all_solvable = false;
+                continue;
+            }
+        };
+
+        match dive::solve(&map) {
+            Some(solution) => {
+                let exit = match solution.exit {
+                    Exit::DataPort => "data port",
+                    Exit::Portal => "portal",
+                };
+                println!("{}: {} in {} move(s): {}", path.display(), exit, solution.moves.len(), solution.keys());
+            },
+            None => {
+                println!("{}: unsolvable", path.display());
+                all_solvable = false;
+            },
+        }
+    }
+
+    if !all_solvable {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,244 @@
+// Cyberware: dive upgrades bought as parts and fitted in the pod.
+//
+// Owned parts live in the inventory under their item ids; which of them are
+// fitted is kept as `equip_<item>` quest flags so it is saved with the story.
+// A rig is the fitted set for one dive, with its charges counting down.
+
+use crate::dive::{DiveMap, Direction, Position, Step, Tile};
+use crate::inventory::Inventory;
+use crate::quest::Quest;
+
+pub const CYBERWARE_SLOTS: usize = 2;
+
+// How much of the usual trace rate gets through a trace damper.
+pub const DAMPED_TRACE: f32 = 0.75;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cyberware {
+    LavaShield,
+    DiveBrake,
+    WallBreaker,
+    TraceDamper,
+}
+
+impl Cyberware {
+    pub const ALL: [Cyberware; 4] = [
+        Cyberware::LavaShield,
+        Cyberware::DiveBrake,
+        Cyberware::WallBreaker,
+        Cyberware::TraceDamper,
+    ];
+
+    pub fn item(self) -> &'static str {
+        match self {
+            Cyberware::LavaShield => "lava_shield",
+            Cyberware::DiveBrake => "dive_brake",
+            Cyberware::WallBreaker => "wall_breaker",
+            Cyberware::TraceDamper => "trace_damper",
+        }
+    }
+
+    pub fn from_item(item: &str) -> Option<Cyberware> {
+        Cyberware::ALL.into_iter().find(|cyberware| cyberware.item() == item)
+    }
+
+    pub fn flag(self) -> String {
+        format!("equip_{}", self.item())
+    }
+
+    // Uses per dive; the damper works all the time.
+    fn charges(self) -> u32 {
+        match self {
+            Cyberware::LavaShield => 1,
+            Cyberware::DiveBrake => 3,
+            Cyberware::WallBreaker => 1,
+            Cyberware::TraceDamper => 0,
+        }
+    }
+}
+
+pub fn owned(inventory: &Inventory) -> Vec<Cyberware> {
+    Cyberware::ALL.into_iter().filter(|cyberware| inventory.has(cyberware.item())).collect()
+}
+
+pub fn equipped(quest: &Quest, inventory: &Inventory) -> Vec<Cyberware> {
+    owned(inventory).into_iter().filter(|cyberware| quest.has(&cyberware.flag())).collect()
+}
+
+// Fits or removes a part. Fails when it is not owned or every slot is taken.
+pub fn toggle(quest: &mut Quest, inventory: &Inventory, cyberware: Cyberware) -> bool {
+    let flag = cyberware.flag();
+    if quest.has(&flag) {
+        quest.clear(&flag);
+        return true;
+    }
+
+    if !inventory.has(cyberware.item()) || equipped(quest, inventory).len() >= CYBERWARE_SLOTS {
+        return false;
+    }
+
+    quest.set(&flag);
+    true
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Rig {
+    pub fitted: Vec<Cyberware>,
+    shields: u32,
+    brakes: u32,
+    breakers: u32,
+    // Armed at rest for the next slide.
+    pub braking: bool,
+    pub breaching: bool,
+}
+
+impl Rig {
+    pub fn fit(fitted: &[Cyberware]) -> Rig {
+        let charges = |kind: Cyberware| fitted.iter().filter(|&&cyberware| cyberware == kind).map(|cyberware| cyberware.charges()).sum();
+
+        Rig {
+            fitted: fitted.to_vec(),
+            shields: charges(Cyberware::LavaShield),
+            brakes: charges(Cyberware::DiveBrake),
+            breakers: charges(Cyberware::WallBreaker),
+            braking: false,
+            breaching: false,
+        }
+    }
+
+    pub fn trace_factor(&self) -> f32 {
+        if self.fitted.contains(&Cyberware::TraceDamper) {
+            DAMPED_TRACE
+        } else {
+            1.0
+        }
+    }
+
+    // The next slide stops after one tile.
+    pub fn arm_brake(&mut self) -> bool {
+        if self.braking || self.brakes == 0 {
+            return false;
+        }
+
+        self.brakes -= 1;
+        self.braking = true;
+        true
+    }
+
+    // The next slide breaks through the first inner wall it runs into.
+    pub fn arm_breaker(&mut self) -> bool {
+        if self.breaching || self.breakers == 0 {
+            return false;
+        }
+
+        self.breakers -= 1;
+        self.breaching = true;
+        true
+    }
+
+    pub fn blocks(&self, map: &DiveMap, from: Position, direction: Direction) -> bool {
+        map.step(from, direction) == Step::Blocked && !(self.breaching && breakable(map, ahead(from, direction)))
+    }
+
+    // One tile of a slide, with the fitted parts bending the map's rules.
+    pub fn step(&mut self, map: &mut DiveMap, from: Position, direction: Direction) -> Step {
+        let step = match map.step(from, direction) {
+            Step::Lava(_) if self.shields > 0 => {
+                self.shields -= 1;
+                Step::Blocked
+            },
+            Step::Blocked if self.breaching && breakable(map, ahead(from, direction)) => {
+                let target = ahead(from, direction);
+                map.set_tile(target, Tile::Floor);
+                self.breaching = false;
+                Step::Moved(target, None)
+            },
+            Step::Moved(next, Some(_)) if self.braking => Step::Moved(next, None),
+            step => step,
+        };
+
+        if !matches!(step, Step::Moved(_, Some(_))) {
+            self.braking = false;
+        }
+
+        step
+    }
+
+    pub fn hud_text(&self) -> String {
+        let mut parts = Vec::new();
+        if self.fitted.contains(&Cyberware::LavaShield) {
+            parts.push(format!("Shield: {}", self.shields));
+        }
+        if self.fitted.contains(&Cyberware::DiveBrake) {
+            parts.push(format!("[B] Brake: {}{}", self.brakes, if self.braking { " ON" } else { "" }));
+        }
+        if self.fitted.contains(&Cyberware::WallBreaker) {
+            parts.push(format!("[X] Breaker: {}{}", self.breakers, if self.breaching { " ON" } else { "" }));
+        }
+        if self.fitted.contains(&Cyberware::TraceDamper) {
+            parts.push(String::from("Damper"));
+        }
+
+        parts.join("  ")
+    }
+}
+
+fn ahead((x, y): Position, direction: Direction) -> Position {
+    let (dx, dy) = direction.offset();
+    (x + dx, y + dy)
+}
+
+// The outer ring of walls stays, so a breach never leads off the map.
+fn breakable(map: &DiveMap, (x, y): Position) -> bool {
+    map.tile((x, y)) == Tile::Wall
+        && x > 0
+        && y > 0
+        && (x as usize) + 1 < map.width
+        && (y as usize) + 1 < map.height
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_need_owning_and_a_free_slot() {
+        let mut inventory = Inventory::new();
+        let mut quest = Quest::new();
+        assert!(!toggle(&mut quest, &inventory, Cyberware::LavaShield));
+
+        for cyberware in Cyberware::ALL {
+            inventory.give(cyberware.item(), 1);
+        }
+        assert!(toggle(&mut quest, &inventory, Cyberware::LavaShield));
+        assert!(toggle(&mut quest, &inventory, Cyberware::DiveBrake));
+        assert!(!toggle(&mut quest, &inventory, Cyberware::WallBreaker));
+
+        assert!(toggle(&mut quest, &inventory, Cyberware::LavaShield));
+        assert_eq!(equipped(&quest, &inventory), vec![Cyberware::DiveBrake]);
+    }
+
+    #[test]
+    fn rigs_bend_the_slide_rules() {
+        let mut map = DiveMap::parse("#######\n#P ~  #\n#  #  #\n#######\n");
+        let mut rig = Rig::fit(&[Cyberware::LavaShield, Cyberware::DiveBrake, Cyberware::WallBreaker]);
+
+        // The shield holds once, at the edge of the lava.
+        assert_eq!(rig.step(&mut map, (2, 1), Direction::Right), Step::Blocked);
+        assert_eq!(rig.step(&mut map, (2, 1), Direction::Right), Step::Lava((3, 1)));
+
+        assert!(rig.arm_brake());
+        assert_eq!(rig.step(&mut map, (1, 2), Direction::Right), Step::Moved((2, 2), None));
+        assert!(!rig.braking);
+
+        assert!(rig.blocks(&map, (2, 2), Direction::Right));
+        assert!(rig.arm_breaker());
+        assert!(!rig.blocks(&map, (2, 2), Direction::Right));
+        assert_eq!(rig.step(&mut map, (2, 2), Direction::Right), Step::Moved((3, 2), None));
+        assert_eq!(map.tile((3, 2)), Tile::Floor);
+
+        // The outer walls do not break.
+        assert!(!rig.arm_breaker());
+        assert!(rig.blocks(&map, (5, 2), Direction::Down));
+    }
+}
@@ -0,0 +1,526 @@
+// Grid model of the deep dive puzzles, shared by the game and the solver.
+//
+// Maps are plain text, one character per tile:
+//   '#' wall, '~' lava, '@' data port, '$' exit portal,
+//   '<' '>' '^' 'v' conveyors, '+' stopper, '*' data fragment, 'P' spawn,
+//   anything else is floor.
+//
+// Data fragments are optional pickups that slides pass straight over.
+//
+// Without a 'P' the player spawns in the middle of the map.
+
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const MAP_DIR: &str = "assets/dives";
+
+pub const DIVE_SETS_PATH: &str = "assets/dive_sets.txt";
+
+pub const SPAWN_CHAR: char = 'P';
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [Direction::Left, Direction::Right, Direction::Up, Direction::Down];
+
+    // Map coordinates are (column, line), and the dive draws the first line of
+    // the file at the top of the screen.
+    pub fn offset(self) -> (i32, i32) {
+        match self {
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+        }
+    }
+
+    pub fn key(self) -> char {
+        match self {
+            Direction::Left => 'A',
+            Direction::Right => 'D',
+            Direction::Up => 'W',
+            Direction::Down => 'S',
+        }
+    }
+
+    pub fn from_key(key: char) -> Option<Direction> {
+        Direction::ALL.into_iter().find(|direction| direction.key() == key.to_ascii_uppercase())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tile {
+    Floor,
+    Wall,
+    Lava,
+    DataPort,
+    Portal,
+    Conveyor(Direction),
+    Stopper,
+    Fragment,
+}
+
+impl Tile {
+    pub fn from_char(c: char) -> Tile {
+        match c {
+            '#' => Tile::Wall,
+            '~' => Tile::Lava,
+            '@' => Tile::DataPort,
+            '$' => Tile::Portal,
+            '<' => Tile::Conveyor(Direction::Left),
+            '>' => Tile::Conveyor(Direction::Right),
+            '^' => Tile::Conveyor(Direction::Up),
+            'v' => Tile::Conveyor(Direction::Down),
+            '+' => Tile::Stopper,
+            '*' => Tile::Fragment,
+            _ => Tile::Floor,
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            Tile::Floor => ' ',
+            Tile::Wall => '#',
+            Tile::Lava => '~',
+            Tile::DataPort => '@',
+            Tile::Portal => '$',
+            Tile::Conveyor(Direction::Left) => '<',
+            Tile::Conveyor(Direction::Right) => '>',
+            Tile::Conveyor(Direction::Up) => '^',
+            Tile::Conveyor(Direction::Down) => 'v',
+            Tile::Stopper => '+',
+            Tile::Fragment => '*',
+        }
+    }
+}
+
+pub type Position = (i32, i32);
+
+// Result of advancing a slide by one tile.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Step {
+    Moved(Position, Option<Direction>),
+    Blocked,
+    Lava(Position),
+    DataPort(Position),
+    Portal(Position),
+}
+
+// Result of a whole slide, from a key press until the player rests or leaves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Slide {
+    Stopped(Position),
+    Lava,
+    DataPort,
+    Portal,
+    Lost,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiveMap {
+    pub width: usize,
+    pub height: usize,
+    pub spawn: Position,
+    tiles: Vec<Vec<Tile>>,
+}
+
+impl DiveMap {
+    pub fn new(width: usize, height: usize) -> DiveMap {
+        DiveMap::from_tiles(vec![vec![Tile::Floor; width]; height])
+    }
+
+    pub fn parse(text: &str) -> DiveMap {
+        let mut map = DiveMap::from_tiles(text
+            .split('\n')
+            .map(|line| line.chars().map(Tile::from_char).collect())
+            .collect());
+
+        for (y, line) in text.split('\n').enumerate() {
+            if let Some(x) = line.chars().position(|c| c == SPAWN_CHAR) {
+                map.spawn = (x as i32, y as i32);
+            }
+        }
+
+        map
+    }
+
+    fn from_tiles(tiles: Vec<Vec<Tile>>) -> DiveMap {
+        let width = tiles.iter().map(|row| row.len()).max().unwrap_or(0);
+        let height = tiles.len();
+
+        DiveMap {
+            width,
+            height,
+            spawn: (width as i32 / 2, height as i32 / 2),
+            tiles,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<DiveMap> {
+        Ok(DiveMap::parse(&fs::read_to_string(path)?))
+    }
+
+    // Same layout as the files in the dive folder, without a trailing newline.
+    pub fn to_text(&self) -> String {
+        let mut lines = Vec::new();
+        for y in 0..self.height as i32 {
+            let mut line: String = self.tiles[y as usize].iter().map(|tile| tile.to_char()).collect();
+            if self.spawn.1 == y && self.tile(self.spawn) == Tile::Floor {
+                let x = self.spawn.0 as usize;
+                while line.chars().count() <= x {
+                    line.push(' ');
+                }
+                line = line.chars().enumerate().map(|(i, c)| if i == x { SPAWN_CHAR } else { c }).collect();
+            }
+            lines.push(line);
+        }
+
+        lines.join("\n")
+    }
+
+    // Grows or crops the map from the bottom right, keeping the spawn inside.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        self.tiles.resize(height, Vec::new());
+        for row in self.tiles.iter_mut() {
+            row.resize(width, Tile::Floor);
+        }
+
+        self.width = width;
+        self.height = height;
+        self.spawn = (self.spawn.0.min(width as i32 - 1), self.spawn.1.min(height as i32 - 1));
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn in_bounds(&self, (x, y): Position) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    pub fn tile(&self, (x, y): Position) -> Tile {
+        if x < 0 || y < 0 {
+            return Tile::Floor;
+        }
+
+        self.tiles
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .copied()
+            .unwrap_or(Tile::Floor)
+    }
+
+    // Which sides have a wall next to them, as bits N = 1, E = 2, S = 4, W = 8.
+    // Used to pick the wall tile that draws edges only where the wall ends.
+    pub fn wall_mask(&self, (x, y): Position) -> usize {
+        let mut mask = 0;
+        for (bit, (dx, dy)) in [(1, (0, -1)), (2, (1, 0)), (4, (0, 1)), (8, (-1, 0))] {
+            if self.tile((x + dx, y + dy)) == Tile::Wall {
+                mask |= bit;
+            }
+        }
+
+        mask
+    }
+
+    pub fn fragments(&self) -> Vec<Position> {
+        let mut fragments = Vec::new();
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                if self.tile((x, y)) == Tile::Fragment {
+                    fragments.push((x, y));
+                }
+            }
+        }
+
+        fragments
+    }
+
+    pub fn set_tile(&mut self, (x, y): Position, tile: Tile) {
+        if !self.in_bounds((x, y)) {
+            return;
+        }
+
+        let row = &mut self.tiles[y as usize];
+        if row.len() <= x as usize {
+            row.resize(x as usize + 1, Tile::Floor);
+        }
+        row[x as usize] = tile;
+    }
+
+    pub fn step(&self, (x, y): Position, direction: Direction) -> Step {
+        let (dx, dy) = direction.offset();
+        let target = (x + dx, y + dy);
+
+        match self.tile(target) {
+            Tile::Wall => Step::Blocked,
+            Tile::Lava => Step::Lava(target),
+            Tile::DataPort => Step::DataPort(target),
+            Tile::Portal => Step::Portal(target),
+            Tile::Conveyor(pushed) => Step::Moved(target, Some(pushed)),
+            Tile::Stopper => Step::Moved(target, None),
+            Tile::Floor | Tile::Fragment => Step::Moved(target, Some(direction)),
+        }
+    }
+
+    pub fn slide(&self, from: Position, direction: Direction) -> Slide {
+        let mut position = from;
+        let mut sliding = Some(direction);
+        let mut seen = HashSet::new();
+
+        while let Some(direction) = sliding {
+            if !self.in_bounds(position) || !seen.insert((position, direction)) {
+                return Slide::Lost;
+            }
+
+            match self.step(position, direction) {
+                Step::Moved(next, next_sliding) => {
+                    position = next;
+                    sliding = next_sliding;
+                },
+                Step::Blocked => sliding = None,
+                Step::Lava(_) => return Slide::Lava,
+                Step::DataPort(_) => return Slide::DataPort,
+                Step::Portal(_) => return Slide::Portal,
+            }
+        }
+
+        if self.in_bounds(position) {
+            Slide::Stopped(position)
+        } else {
+            Slide::Lost
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Exit {
+    DataPort,
+    Portal,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Solution {
+    pub moves: Vec<Direction>,
+    pub exit: Exit,
+}
+
+impl Solution {
+    pub fn keys(&self) -> String {
+        self.moves.iter().map(|direction| direction.key()).collect()
+    }
+}
+
+// Breadth first search over resting positions, so the first exit found uses
+// the fewest key presses. Lava sends the player back to the spawn, which is
+// always visited already, so it never shortens a route.
+pub fn solve(map: &DiveMap) -> Option<Solution> {
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+
+    visited.insert(map.spawn);
+    queue.push_back((map.spawn, Vec::new()));
+
+    while let Some((position, moves)) = queue.pop_front() {
+        for direction in Direction::ALL {
+            let mut next_moves: Vec<Direction> = moves.clone();
+            next_moves.push(direction);
+
+            match map.slide(position, direction) {
+                Slide::DataPort => return Some(Solution { moves: next_moves, exit: Exit::DataPort }),
+                Slide::Portal => return Some(Solution { moves: next_moves, exit: Exit::Portal }),
+                Slide::Stopped(next) => {
+                    if visited.insert(next) {
+                        queue.push_back((next, next_moves));
+                    }
+                },
+                Slide::Lava | Slide::Lost => (),
+            }
+        }
+    }
+
+    None
+}
+
+// Every map file in the dive folder, sorted by name.
+pub fn map_paths<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("txt") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+// The maps of one dive, keyed by the progress step that sends the player in.
+// The list file has one dive per line, `<key> <map> <map> ...`, in the order
+// the keys are fetched, and the line index is the dive's data bank.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DiveSet {
+    pub key: String,
+    pub owner: String,
+    pub levels: Vec<String>,
+}
+
+impl DiveSet {
+    pub fn parse_all(text: &str) -> Vec<DiveSet> {
+        text.lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .filter_map(|line| {
+                let mut names = line.split_whitespace().map(String::from);
+                let key = names.next()?;
+                let owner = names.next()?;
+
+                Some(DiveSet { key, owner, levels: names.collect() })
+            })
+            .collect()
+    }
+
+    pub fn load_all<P: AsRef<Path>>(path: P) -> io::Result<Vec<DiveSet>> {
+        Ok(DiveSet::parse_all(&fs::read_to_string(path)?))
+    }
+
+    pub fn map_path(&self, level: usize) -> Option<String> {
+        self.levels.get(level).map(|name| format!("{}/{}.txt", MAP_DIR, name))
+    }
+}
+
+#[cfg(test)]
+pub fn assert_solvable<P: AsRef<Path>>(path: P) -> Solution {
+    let path = path.as_ref();
+    let map = DiveMap::load(path).unwrap_or_else(|e| panic!("Cannot open {}: {}", path.display(), e));
+
+    solve(&map).unwrap_or_else(|| panic!("{} has no route to @ or $", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_dive_map_is_solvable() {
+        let paths = map_paths(MAP_DIR).unwrap();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            assert_solvable(path);
+        }
+    }
+
+    #[test]
+    fn dives_end_at_a_portal() {
+        let sets = DiveSet::load_all(DIVE_SETS_PATH).unwrap();
+        assert!(!sets.is_empty());
+
+        for set in sets {
+            assert!(!set.levels.is_empty(), "{} has no levels", set.key);
+
+            for level in 0..set.levels.len() {
+                let exit = if level + 1 == set.levels.len() { Exit::Portal } else { Exit::DataPort };
+                assert_eq!(assert_solvable(set.map_path(level).unwrap()).exit, exit);
+            }
+        }
+    }
+
+    #[test]
+    fn dive_sets_skip_comments_and_blank_lines() {
+        let sets = DiveSet::parse_all("# key owner maps\n\nfirst_key corp a b  # two levels\nfinal_key corp c\n");
+
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0].owner, "corp");
+        assert_eq!(sets[0].levels, vec!["a", "b"]);
+        assert_eq!(sets[1].map_path(0), Some(format!("{}/c.txt", MAP_DIR)));
+        assert_eq!(sets[1].map_path(1), None);
+    }
+
+    #[test]
+    fn first_map_takes_one_slide_up() {
+        let solution = assert_solvable("assets/dives/map0-0.txt");
+
+        assert_eq!(solution.moves, vec![Direction::Up]);
+        assert_eq!(solution.exit, Exit::DataPort);
+    }
+
+    #[test]
+    fn slides_stop_at_walls_and_stoppers() {
+        let map = DiveMap::parse("#####\n#   #\n#  +#\n#   #\n#####");
+
+        assert_eq!(map.slide(map.spawn, Direction::Left), Slide::Stopped((1, 2)));
+        assert_eq!(map.slide(map.spawn, Direction::Right), Slide::Stopped((3, 2)));
+        assert_eq!(map.slide((1, 2), Direction::Right), Slide::Stopped((3, 2)));
+    }
+
+    #[test]
+    fn conveyors_redirect_and_loops_are_lost() {
+        let map = DiveMap::parse("#####\n#   #\n#  ^#\n#   @\n#####");
+        assert_eq!(map.slide(map.spawn, Direction::Right), Slide::Stopped((3, 1)));
+
+        let looped = DiveMap::parse("#####\n#> v#\n#   #\n#^ <#\n#####");
+        assert_eq!(looped.slide((1, 2), Direction::Up), Slide::Lost);
+    }
+
+    #[test]
+    fn wide_maps_spawn_in_the_middle_of_each_axis() {
+        let map = DiveMap::parse("###########\n#         #\n#        @#\n###########");
+
+        assert_eq!((map.width, map.height), (11, 4));
+        assert_eq!(map.spawn, (5, 2));
+        assert_eq!(map.slide(map.spawn, Direction::Up), Slide::Stopped((5, 1)));
+        assert_eq!(map.slide(map.spawn, Direction::Right), Slide::DataPort);
+    }
+
+    #[test]
+    fn wall_masks_mark_neighbouring_walls() {
+        let map = DiveMap::parse("###\n# #\n###");
+
+        assert_eq!(map.wall_mask((0, 0)), 2 | 4);
+        assert_eq!(map.wall_mask((1, 0)), 2 | 8);
+        assert_eq!(map.wall_mask((1, 1)), 1 | 2 | 4 | 8);
+        assert_eq!(map.wall_mask((2, 1)), 1 | 4);
+    }
+
+    #[test]
+    fn spawn_markers_round_trip() {
+        let mut map = DiveMap::parse("#####\n#P  #\n#   @\n#####");
+        assert_eq!(map.spawn, (1, 1));
+        assert_eq!(map.tile(map.spawn), Tile::Floor);
+
+        map.resize(6, 5);
+        map.spawn = (5, 3);
+        assert_eq!(DiveMap::parse(&map.to_text()).spawn, (5, 3));
+        assert_eq!(map.to_text(), "##### \n#   # \n#   @ \n#####P\n      ");
+    }
+
+    #[test]
+    fn slides_pass_over_fragments() {
+        let map = DiveMap::parse("#####\n#*  #\n#  *@\n#####");
+
+        assert_eq!(map.fragments(), vec![(1, 1), (3, 2)]);
+        assert_eq!(map.slide((2, 1), Direction::Left), Slide::Stopped((1, 1)));
+        assert_eq!(map.slide(map.spawn, Direction::Right), Slide::DataPort);
+        assert_eq!(DiveMap::parse(&map.to_text()).fragments(), map.fragments());
+    }
+
+    #[test]
+    fn lava_only_maps_are_unsolvable() {
+        let map = DiveMap::parse("##~##\n#   #\n~   ~\n#   #\n##~##");
+
+        assert_eq!(map.slide(map.spawn, Direction::Up), Slide::Lava);
+        assert_eq!(solve(&map), None);
+    }
+}
@@ -0,0 +1,194 @@
+// Seeded generation of deep dive levels.
+//
+// Layouts are rolled at random and only kept when the solver finds the goal
+// within the requested number of moves, so every generated level is winnable.
+
+use crate::dive::{self, Direction, DiveMap, Exit, Position, Solution, Tile};
+
+// SplitMix64, small and stable across platforms so a seed always makes the
+// same level.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, probability: f32) -> bool {
+        ((self.next_u64() >> 40) as f32 / (1u64 << 24) as f32) < probability
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Difficulty {
+    pub size: usize,
+    pub wall_density: f32,
+    pub lava_density: f32,
+    pub conveyor_density: f32,
+    pub stopper_density: f32,
+    pub min_moves: usize,
+    pub max_moves: usize,
+    pub exit: Exit,
+    pub attempts: u32,
+}
+
+impl Difficulty {
+    pub fn new(min_moves: usize, max_moves: usize) -> Self {
+        Difficulty {
+            size: 9,
+            wall_density: 0.15,
+            lava_density: 0.05,
+            conveyor_density: 0.0,
+            stopper_density: 0.0,
+            min_moves,
+            max_moves,
+            exit: Exit::DataPort,
+            attempts: 500,
+        }
+    }
+
+    // Settings used in game when a dive is generated rather than loaded.
+    // Later banks and levels need more moves and mix in the extra tiles.
+    pub fn for_level(bank: u32, level: u32, last_level: bool) -> Self {
+        let min_moves = 2 + bank as usize + level as usize;
+
+        let mut difficulty = Difficulty::new(min_moves, min_moves + 3);
+        difficulty.lava_density = 0.04 + 0.02 * level as f32;
+        if bank > 0 {
+            difficulty.stopper_density = 0.03;
+        }
+        if bank > 1 {
+            difficulty.conveyor_density = 0.03;
+        }
+        if last_level {
+            difficulty.exit = Exit::Portal;
+        }
+
+        difficulty
+    }
+}
+
+// Mixes the dive seed with the bank and level so each level is different but
+// still reproducible from the one seed.
+pub fn level_seed(seed: u64, bank: u32, level: u32) -> u64 {
+    let mut rng = Rng::new(seed ^ ((bank as u64) << 32) ^ level as u64);
+    rng.next_u64()
+}
+
+pub fn generate(seed: u64, difficulty: &Difficulty) -> Option<(DiveMap, Solution)> {
+    let mut rng = Rng::new(seed);
+
+    let size = difficulty.size.max(5);
+    let goal = match difficulty.exit {
+        Exit::DataPort => Tile::DataPort,
+        Exit::Portal => Tile::Portal,
+    };
+
+    for _ in 0..difficulty.attempts {
+        let mut map = roll_layout(&mut rng, size, difficulty);
+
+        let mut candidates: Vec<Position> = Vec::new();
+        for y in 0..size as i32 {
+            for x in 0..size as i32 {
+                let corner = (x == 0 || x == size as i32 - 1) && (y == 0 || y == size as i32 - 1);
+                let tile = map.tile((x, y));
+                if !corner && (x, y) != map.spawn && (tile == Tile::Floor || tile == Tile::Wall) {
+                    candidates.push((x, y));
+                }
+            }
+        }
+
+        while !candidates.is_empty() {
+            let position = candidates.swap_remove(rng.below(candidates.len()));
+            let previous = map.tile(position);
+            map.set_tile(position, goal);
+
+            if let Some(solution) = dive::solve(&map) {
+                let moves = solution.moves.len();
+                if moves >= difficulty.min_moves && moves <= difficulty.max_moves {
+                    return Some((map, solution));
+                }
+            }
+
+            map.set_tile(position, previous);
+        }
+    }
+
+    None
+}
+
+fn roll_layout(rng: &mut Rng, size: usize, difficulty: &Difficulty) -> DiveMap {
+    let mut map = DiveMap::new(size, size);
+    let last = size as i32 - 1;
+
+    for y in 0..size as i32 {
+        for x in 0..size as i32 {
+            let tile = if x == 0 || y == 0 || x == last || y == last {
+                if rng.chance(difficulty.lava_density) {
+                    Tile::Lava
+                } else {
+                    Tile::Wall
+                }
+            } else if (x, y) == map.spawn {
+                Tile::Floor
+            } else if rng.chance(difficulty.wall_density) {
+                Tile::Wall
+            } else if rng.chance(difficulty.lava_density) {
+                Tile::Lava
+            } else if rng.chance(difficulty.stopper_density) {
+                Tile::Stopper
+            } else if rng.chance(difficulty.conveyor_density) {
+                Tile::Conveyor(Direction::ALL[rng.below(Direction::ALL.len())])
+            } else {
+                Tile::Floor
+            };
+
+            map.set_tile((x, y), tile);
+        }
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_makes_the_same_level() {
+        let difficulty = Difficulty::for_level(1, 1, false);
+
+        let (first, _) = generate(42, &difficulty).unwrap();
+        let (second, _) = generate(42, &difficulty).unwrap();
+
+        assert_eq!(first.to_text(), second.to_text());
+    }
+
+    #[test]
+    fn generated_levels_hit_the_move_target() {
+        for bank in 0..3 {
+            for level in 0..3 {
+                let difficulty = Difficulty::for_level(bank, level, level == 2);
+                let (map, solution) = generate(level_seed(7, bank, level), &difficulty).unwrap();
+
+                let reparsed = DiveMap::parse(&map.to_text());
+                assert_eq!(dive::solve(&reparsed), Some(solution.clone()));
+                assert_eq!(solution.exit, difficulty.exit);
+                assert!(solution.moves.len() >= difficulty.min_moves);
+                assert!(solution.moves.len() <= difficulty.max_moves);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// What the player carries: data keys, extracted data and other items, each
+// with a count.
+//
+// Item ids are short names like `first_key`; the names shown to the player
+// come from a separate text file, `<id> <display name>` per line. The save
+// form is one `<id> <count>` pair per line.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const ITEM_NAMES_PATH: &str = "assets/texts/items.txt";
+
+pub const STOCK_PATH: &str = "assets/texts/parts_shop/stock.txt";
+
+pub const CREDITS: &str = "credits";
+
+// The data keys handed out by the Figure, in the order they are given, each
+// with the data a dive on it brings back.
+pub const DATA_KEYS: [(&str, &str); 3] = [
+    ("first_key", "first_data"),
+    ("second_key", "second_data"),
+    ("final_key", "final_data"),
+];
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Inventory {
+    items: BTreeMap<String, u32>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Inventory::default()
+    }
+
+    pub fn count(&self, item: &str) -> u32 {
+        self.items.get(item).copied().unwrap_or(0)
+    }
+
+    pub fn has(&self, item: &str) -> bool {
+        self.count(item) > 0
+    }
+
+    pub fn give(&mut self, item: &str, amount: u32) {
+        if amount > 0 {
+            *self.items.entry(item.to_string()).or_insert(0) += amount;
+        }
+    }
+
+    // Takes nothing unless the whole amount is there.
+    pub fn take(&mut self, item: &str, amount: u32) -> bool {
+        let count = self.count(item);
+        if count < amount {
+            return false;
+        }
+
+        if count == amount {
+            self.items.remove(item);
+        } else {
+            self.items.insert(item.to_string(), count - amount);
+        }
+        true
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.items.iter().map(|(item, count)| (item.as_str(), *count))
+    }
+
+    // The data key carried and the data it opens.
+    pub fn data_key(&self) -> Option<(&'static str, &'static str)> {
+        DATA_KEYS.iter().copied().find(|(key, _)| self.has(key))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut inventory = Inventory::new();
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            if let (Some(item), Some(count)) = (parts.next(), parts.next()) {
+                if let Ok(count) = count.parse() {
+                    inventory.give(item, count);
+                }
+            }
+        }
+
+        inventory
+    }
+
+    pub fn to_text(&self) -> String {
+        self.items
+            .iter()
+            .map(|(item, count)| format!("{} {}\n", item, count))
+            .collect()
+    }
+}
+
+pub fn parse_item_names(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let (id, name) = line.trim().split_once(char::is_whitespace)?;
+            Some((id.to_string(), name.trim().to_string()))
+        })
+        .collect()
+}
+
+pub fn load_item_names<P: AsRef<Path>>(path: P) -> io::Result<BTreeMap<String, String>> {
+    Ok(parse_item_names(&fs::read_to_string(path)?))
+}
+
+// What a vendor sells, `<item> <price>` per line, in the order listed.
+pub fn parse_stock(text: &str) -> Vec<(String, u32)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let item = parts.next().filter(|item| !item.starts_with('#'))?;
+            let price = parts.next()?.parse().ok()?;
+
+            Some((item.to_string(), price))
+        })
+        .collect()
+}
+
+pub fn load_stock<P: AsRef<Path>>(path: P) -> io::Result<Vec<(String, u32)>> {
+    Ok(parse_stock(&fs::read_to_string(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_are_only_taken_when_there_are_enough() {
+        let mut inventory = Inventory::new();
+        inventory.give("first_key", 1);
+        inventory.give("battery", 3);
+
+        assert!(!inventory.take("battery", 4));
+        assert!(inventory.take("battery", 3));
+        assert!(!inventory.has("battery"));
+        assert_eq!(inventory.data_key(), Some(("first_key", "first_data")));
+        assert_eq!(Inventory::parse(&inventory.to_text()), inventory);
+    }
+
+    #[test]
+    fn every_data_item_and_part_has_a_name() {
+        let names = load_item_names(ITEM_NAMES_PATH).unwrap();
+        let stock = load_stock(STOCK_PATH).unwrap();
+        assert!(!stock.is_empty());
+
+        let data_items = DATA_KEYS.iter().flat_map(|(key, data)| [*key, *data]);
+        for item in data_items.chain(stock.iter().map(|(item, _)| item.as_str())) {
+            assert!(names.contains_key(item), "{} has no name", item);
+        }
+    }
+}
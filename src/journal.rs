@@ -0,0 +1,101 @@
+// Quest journal objectives.
+//
+// Objectives live in a text file next to the dialog, one per line:
+// `<start flag> <done flag> <text>`. An objective is active once its start
+// flag is set (`-` for from the beginning) and completed once its done flag
+// is set.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::quest::Quest;
+
+pub const JOURNAL_PATH: &str = "assets/texts/journal.txt";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Objective {
+    pub start: Option<String>,
+    pub done: String,
+    pub text: String,
+}
+
+impl Objective {
+    pub fn is_active(&self, quest: &Quest) -> bool {
+        let started = match &self.start {
+            Some(flag) => quest.has(flag),
+            None => true,
+        };
+
+        started && !self.is_completed(quest)
+    }
+
+    pub fn is_completed(&self, quest: &Quest) -> bool {
+        quest.has(&self.done)
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Journal {
+    pub objectives: Vec<Objective>,
+}
+
+impl Journal {
+    pub fn parse(text: &str) -> Self {
+        let mut objectives = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, char::is_whitespace);
+            if let (Some(start), Some(done), Some(text)) = (parts.next(), parts.next(), parts.next()) {
+                objectives.push(Objective {
+                    start: if start == "-" { None } else { Some(start.to_string()) },
+                    done: done.to_string(),
+                    text: text.trim().to_string(),
+                });
+            }
+        }
+
+        Journal { objectives }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Journal::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn active<'a>(&'a self, quest: &'a Quest) -> impl Iterator<Item = &'a Objective> {
+        self.objectives.iter().filter(move |objective| objective.is_active(quest))
+    }
+
+    pub fn completed<'a>(&'a self, quest: &'a Quest) -> impl Iterator<Item = &'a Objective> {
+        self.objectives.iter().filter(move |objective| objective.is_completed(quest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objectives_follow_the_quest_flags() {
+        let journal = Journal::parse("# start done text\n- talk Find the Figure.\ntalk first_key  Go to the alley.\n");
+        let mut quest = Quest::new();
+
+        let texts = |objectives: Vec<&Objective>| objectives.iter().map(|o| o.text.clone()).collect::<Vec<_>>();
+        assert_eq!(texts(journal.active(&quest).collect()), vec!["Find the Figure."]);
+
+        quest.set("talk");
+        assert_eq!(texts(journal.active(&quest).collect()), vec!["Go to the alley."]);
+        assert_eq!(texts(journal.completed(&quest).collect()), vec!["Find the Figure."]);
+    }
+
+    #[test]
+    fn the_journal_file_parses() {
+        let journal = Journal::load(JOURNAL_PATH).unwrap();
+
+        assert!(journal.active(&Quest::new()).count() > 0);
+    }
+}
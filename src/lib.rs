@@ -0,0 +1,13 @@
+pub mod cyberware;
+pub mod dive;
+pub mod generator;
+pub mod inventory;
+pub mod journal;
+pub mod npc;
+pub mod quest;
+pub mod replay;
+pub mod reputation;
+pub mod save;
+pub mod scores;
+pub mod settings;
+pub mod story;
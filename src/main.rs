@@ -25,7 +25,6 @@ fn setup(
 ) {
     for mut window in windows.iter_mut() {
         window.set_title(String::from("CyberHarvest: Data Breach"));
-        window.set_resizable(false);
     }
 
     commands.spawn(Camera2dBundle {
@@ -0,0 +1,115 @@
+// Where the characters stand as the story goes on.
+//
+// The placement file has one line per appearance: `<room> <npc> <x>` and then
+// conditions that all have to hold. `flag` and `!flag` test quest flags, and
+// `at:step,step` needs the current story step to be one of those listed. An
+// NPC can have several lines to move between rooms.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::quest::Quest;
+
+pub const NPCS_PATH: &str = "assets/npcs.txt";
+
+// Every NPC the placement file can use: name there, name shown to the player
+// and talking id, which numbers its dialog files.
+pub const NPC_KINDS: [(&str, &str, i32); 6] = [
+    ("robot", "Floating Robot", 1),
+    ("figure", "Mysterious Figure", 2),
+    ("shopkeeper", "Shopkeeper", 3),
+    ("barista", "Barista", 4),
+    ("marlo", "Marlo", 5),
+    ("juno", "Juno", 6),
+];
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    Flag(String),
+    NotFlag(String),
+    AtStep(Vec<String>),
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Condition {
+        if let Some(steps) = text.strip_prefix("at:") {
+            Condition::AtStep(steps.split(',').map(String::from).collect())
+        } else if let Some(flag) = text.strip_prefix('!') {
+            Condition::NotFlag(flag.to_string())
+        } else {
+            Condition::Flag(text.to_string())
+        }
+    }
+
+    pub fn holds(&self, quest: &Quest, step: &str) -> bool {
+        match self {
+            Condition::Flag(flag) => quest.has(flag),
+            Condition::NotFlag(flag) => !quest.has(flag),
+            Condition::AtStep(steps) => steps.iter().any(|at| at == step),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Placement {
+    pub room: String,
+    pub npc: String,
+    pub x: f32,
+    pub conditions: Vec<Condition>,
+}
+
+impl Placement {
+    pub fn parse_all(text: &str) -> Vec<Placement> {
+        text.lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let room = parts.next()?.to_string();
+                let npc = parts.next()?.to_string();
+                let x = parts.next()?.parse().ok()?;
+
+                Some(Placement { room, npc, x, conditions: parts.map(Condition::parse).collect() })
+            })
+            .collect()
+    }
+
+    pub fn load_all<P: AsRef<Path>>(path: P) -> io::Result<Vec<Placement>> {
+        Ok(Placement::parse_all(&fs::read_to_string(path)?))
+    }
+
+    pub fn is_present(&self, quest: &Quest, step: &str) -> bool {
+        self.conditions.iter().all(|condition| condition.holds(quest, step))
+    }
+}
+
+// The NPCs standing in a room right now.
+pub fn present<'a>(placements: &'a [Placement], room: &'a str, quest: &'a Quest, step: &'a str) -> impl Iterator<Item = &'a Placement> {
+    placements.iter().filter(move |placement| placement.room == room && placement.is_present(quest, step))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npcs_follow_flags_and_story_steps() {
+        let placements = Placement::parse_all("# room npc x\ncafe juno 240 !final_key\ncyberway juno 100 final_key\nalley figure -310 at:talk,first_data  # waiting\n");
+        assert_eq!(placements.len(), 3);
+
+        let mut quest = Quest::new();
+        let names = |room: &str, quest: &Quest, step: &str| present(&placements, room, quest, step).map(|placement| placement.npc.clone()).collect::<Vec<_>>();
+        assert_eq!(names("cafe", &quest, "start"), vec!["juno"]);
+        assert!(names("alley", &quest, "start").is_empty());
+        assert_eq!(names("alley", &quest, "first_data"), vec!["figure"]);
+
+        quest.set("final_key");
+        assert!(names("cafe", &quest, "final_key").is_empty());
+        assert_eq!(names("cyberway", &quest, "final_key"), vec!["juno"]);
+    }
+
+    #[test]
+    fn the_placement_file_parses() {
+        assert!(!Placement::load_all(NPCS_PATH).unwrap().is_empty());
+    }
+}
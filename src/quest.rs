@@ -0,0 +1,96 @@
+// Story state as named flags and counters.
+//
+// Rooms, dialog and gates ask for the flags they care about instead of a
+// position on one fixed line, so side content can be done in any order. The
+// text form is one entry per line, `flag <name>` or `count <name> <value>`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Quest {
+    flags: BTreeSet<String>,
+    counters: BTreeMap<String, i64>,
+}
+
+impl Quest {
+    pub fn new() -> Self {
+        Quest::default()
+    }
+
+    pub fn has(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+
+    pub fn set(&mut self, flag: &str) {
+        self.flags.insert(flag.to_string());
+    }
+
+    pub fn clear(&mut self, flag: &str) {
+        self.flags.remove(flag);
+    }
+
+    // Counters that were never touched read as zero.
+    pub fn count(&self, counter: &str) -> i64 {
+        self.counters.get(counter).copied().unwrap_or(0)
+    }
+
+    pub fn set_count(&mut self, counter: &str, value: i64) {
+        if value == 0 {
+            self.counters.remove(counter);
+        } else {
+            self.counters.insert(counter.to_string(), value);
+        }
+    }
+
+    pub fn add(&mut self, counter: &str, amount: i64) {
+        self.set_count(counter, self.count(counter) + amount);
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut quest = Quest::new();
+        for line in text.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts[..] {
+                ["flag", name] => quest.set(name),
+                ["count", name, value] => {
+                    if let Ok(value) = value.parse() {
+                        quest.set_count(name, value);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        quest
+    }
+
+    pub fn to_text(&self) -> String {
+        let flags = self.flags.iter().map(|name| format!("flag {}\n", name));
+        let counters = self.counters.iter().map(|(name, value)| format!("count {} {}\n", name, value));
+
+        flags.chain(counters).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_and_counters_round_trip() {
+        let mut quest = Quest::new();
+        quest.set("talk");
+        quest.set("first_key");
+        quest.clear("first_key");
+        quest.add("credits", 30);
+        quest.add("credits", -5);
+        quest.add("fragments", 2);
+        quest.add("fragments", -2);
+
+        assert!(quest.has("talk"));
+        assert!(!quest.has("first_key"));
+        assert_eq!(quest.count("credits"), 25);
+        assert_eq!(quest.to_text(), "flag talk\ncount credits 25\n");
+        assert_eq!(Quest::parse(&quest.to_text()), quest);
+    }
+}
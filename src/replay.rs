@@ -0,0 +1,185 @@
+// Input logs for dive attempts.
+//
+// A dive is fully determined by its data bank, the generator seed (if any) and
+// the keys pressed while the player was at rest, so that is all a replay keeps:
+//
+//     bank 0
+//     seed 42
+//     cyberware dive_brake
+//     inputs SDZWBA
+//     end traced
+//
+// W/A/S/D are slides, Z is an undo, B arms the dive brake and X the wall
+// breaker. R is a level restart from the pause menu. The seed line is left
+// out for the hand made maps, the cyberware line when nothing was fitted.
+// The end line says how a run that never reached the data was cut short,
+// `traced` by security or `abandoned` from the pause menu.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cyberware::Cyberware;
+use crate::dive::Direction;
+
+pub const REPLAY_DIR: &str = "saves/replays";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiveInput {
+    Slide(Direction),
+    Undo,
+    Brake,
+    Breach,
+    Restart,
+}
+
+impl DiveInput {
+    pub fn key(self) -> char {
+        match self {
+            DiveInput::Slide(direction) => direction.key(),
+            DiveInput::Undo => 'Z',
+            DiveInput::Brake => 'B',
+            DiveInput::Breach => 'X',
+            DiveInput::Restart => 'R',
+        }
+    }
+
+    pub fn from_key(key: char) -> Option<DiveInput> {
+        match key.to_ascii_uppercase() {
+            'Z' => Some(DiveInput::Undo),
+            'B' => Some(DiveInput::Brake),
+            'X' => Some(DiveInput::Breach),
+            'R' => Some(DiveInput::Restart),
+            key => Direction::from_key(key).map(DiveInput::Slide),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiveEnd {
+    TracedOut,
+    Abandoned,
+}
+
+impl DiveEnd {
+    pub fn name(self) -> &'static str {
+        match self {
+            DiveEnd::TracedOut => "traced",
+            DiveEnd::Abandoned => "abandoned",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DiveEnd> {
+        match name {
+            "traced" => Some(DiveEnd::TracedOut),
+            "abandoned" => Some(DiveEnd::Abandoned),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Replay {
+    pub bank: u32,
+    pub seed: Option<u64>,
+    pub cyberware: Vec<Cyberware>,
+    pub inputs: Vec<DiveInput>,
+    pub end: Option<DiveEnd>,
+}
+
+impl Replay {
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let mut replay = Replay::default();
+
+        for line in text.lines() {
+            let (name, value) = match line.trim().split_once(' ') {
+                Some(pair) => pair,
+                None => continue,
+            };
+
+            match name {
+                "bank" => replay.bank = value.trim().parse().map_err(|_| format!("Bad bank: {}", value))?,
+                "seed" => replay.seed = Some(value.trim().parse().map_err(|_| format!("Bad seed: {}", value))?),
+                "cyberware" => {
+                    for item in value.split_whitespace() {
+                        replay.cyberware.push(Cyberware::from_item(item).ok_or(format!("Bad cyberware: {}", item))?);
+                    }
+                },
+                "inputs" => {
+                    for key in value.trim().chars() {
+                        replay.inputs.push(DiveInput::from_key(key).ok_or(format!("Bad input: {}", key))?);
+                    }
+                },
+                "end" => replay.end = Some(DiveEnd::from_name(value.trim()).ok_or(format!("Bad end: {}", value))?),
+                _ => return Err(format!("Unknown replay line: {}", line)),
+            }
+        }
+
+        Ok(replay)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        Replay::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("bank {}\n", self.bank);
+        if let Some(seed) = self.seed {
+            text.push_str(&format!("seed {}\n", seed));
+        }
+        if !self.cyberware.is_empty() {
+            let items: Vec<&str> = self.cyberware.iter().map(|cyberware| cyberware.item()).collect();
+            text.push_str(&format!("cyberware {}\n", items.join(" ")));
+        }
+        text.push_str("inputs ");
+        text.extend(self.inputs.iter().map(|input| input.key()));
+        text.push('\n');
+        if let Some(end) = self.end {
+            text.push_str(&format!("end {}\n", end.name()));
+        }
+
+        text
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_round_trip_through_text() {
+        let replay = Replay {
+            bank: 1,
+            seed: Some(42),
+            cyberware: vec![Cyberware::DiveBrake],
+            inputs: vec![
+                DiveInput::Slide(Direction::Down),
+                DiveInput::Undo,
+                DiveInput::Brake,
+                DiveInput::Restart,
+                DiveInput::Slide(Direction::Left),
+            ],
+            end: Some(DiveEnd::TracedOut),
+        };
+
+        assert_eq!(replay.to_text(), "bank 1\nseed 42\ncyberware dive_brake\ninputs SZBRA\nend traced\n");
+        assert_eq!(Replay::parse(&replay.to_text()), Ok(replay));
+    }
+
+    #[test]
+    fn unknown_inputs_are_rejected() {
+        assert!(Replay::parse("bank 0\ninputs SQ\n").is_err());
+        assert!(Replay::parse("bank 0\ninputs S\nend bored\n").is_err());
+        assert_eq!(Replay::parse("bank 2\ninputs \n").map(|replay| replay.bank), Ok(2));
+    }
+}
@@ -0,0 +1,233 @@
+// Standing with the two corporations, kept as quest counters.
+//
+// Breaching a corporation's databases costs standing with it, and dialog
+// choices can shift it: the reputation file lists `<flag> <faction> <change>`
+// for the choices that do. Standing picks NPC lines, how fast a corporation
+// traces a diver, and can turn an ending.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::quest::Quest;
+
+pub const REPUTATION_PATH: &str = "assets/texts/reputation.txt";
+
+// Lost with a corporation for every dive out of its databases.
+pub const BREACH_COST: i64 = 10;
+
+// Standing at or past these makes a corporation treat the player differently.
+pub const HOSTILE: i64 = -15;
+pub const FRIENDLY: i64 = 15;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Faction {
+    Helionix,
+    Fusiogenic,
+}
+
+impl Faction {
+    pub const ALL: [Faction; 2] = [Faction::Helionix, Faction::Fusiogenic];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Faction::Helionix => "helionix",
+            Faction::Fusiogenic => "fusiogenic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Faction> {
+        Faction::ALL.into_iter().find(|faction| faction.name() == name)
+    }
+
+    pub fn counter(self) -> String {
+        format!("rep_{}", self.name())
+    }
+}
+
+pub fn standing(quest: &Quest, faction: Faction) -> i64 {
+    quest.count(&faction.counter())
+}
+
+pub fn shift(quest: &mut Quest, faction: Faction, amount: i64) {
+    quest.add(&faction.counter(), amount);
+}
+
+// `helionix_hostile`, `fusiogenic_friendly` and so on, for picking dialog.
+pub fn standing_flags(quest: &Quest) -> Vec<String> {
+    Faction::ALL.into_iter()
+        .filter_map(|faction| {
+            let standing = standing(quest, faction);
+            if standing <= HOSTILE {
+                Some(format!("{}_hostile", faction.name()))
+            } else if standing >= FRIENDLY {
+                Some(format!("{}_friendly", faction.name()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Scales a corporation's trace speed: a point of standing is a percent,
+// within a quarter slower and half again as fast.
+pub fn trace_factor(quest: &Quest, faction: Faction) -> f32 {
+    (1.0 - standing(quest, faction) as f32 / 100.0).clamp(0.75, 1.5)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Shift {
+    pub flag: String,
+    pub faction: Faction,
+    pub amount: i64,
+}
+
+impl Shift {
+    pub fn parse_all(text: &str) -> Vec<Shift> {
+        text.lines()
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                match parts[..] {
+                    [flag, faction, amount] if !flag.starts_with('#') => Some(Shift {
+                        flag: flag.to_string(),
+                        faction: Faction::from_name(faction)?,
+                        amount: amount.parse().ok()?,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    pub fn load_all<P: AsRef<Path>>(path: P) -> io::Result<Vec<Shift>> {
+        Ok(Shift::parse_all(&fs::read_to_string(path)?))
+    }
+}
+
+// Sets a flag picked in dialog, with the standing it is worth the first time.
+pub fn pick(quest: &mut Quest, shifts: &[Shift], flag: &str) {
+    if quest.has(flag) {
+        return;
+    }
+
+    quest.set(flag);
+    for shift in shifts.iter().filter(|shift| shift.flag == flag) {
+        self::shift(quest, shift.faction, shift.amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::dive::{DiveSet, DIVE_SETS_PATH};
+    use crate::npc::{Placement, NPCS_PATH, NPC_KINDS};
+    use crate::story::Choice;
+
+    #[test]
+    fn choices_shift_standing_once() {
+        let shifts = Shift::parse_all("# flag faction change\ntip fusiogenic 20\ntip helionix -10\nbad nobody 5\n");
+        assert_eq!(shifts.len(), 2);
+
+        let mut quest = Quest::new();
+        pick(&mut quest, &shifts, "tip");
+        pick(&mut quest, &shifts, "tip");
+
+        assert_eq!(standing(&quest, Faction::Fusiogenic), 20);
+        assert_eq!(standing_flags(&quest), vec!["fusiogenic_friendly"]);
+        assert!((trace_factor(&quest, Faction::Fusiogenic) - 0.8).abs() < 1e-6);
+        assert!((trace_factor(&quest, Faction::Helionix) - 1.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_reputation_file_parses() {
+        let shifts = Shift::load_all(REPUTATION_PATH).unwrap();
+
+        assert!(!shifts.is_empty());
+    }
+
+    // Plays every mix of dialog choices through the story with the real shift
+    // table, dive sets and NPC placements. A standing folder counts as seen
+    // when its NPC is there and the step has no lines of its own for them.
+    #[test]
+    fn every_standing_dialog_can_show() {
+        const STEPS: [&str; 8] = ["start", "talk", "first_key", "first_data", "second_key", "second_data", "final_key", "final_data"];
+        const TEXTS: &str = "assets/texts";
+
+        let shifts = Shift::load_all(REPUTATION_PATH).unwrap();
+        let dives = DiveSet::load_all(DIVE_SETS_PATH).unwrap();
+        let placements = Placement::load_all(NPCS_PATH).unwrap();
+
+        let rooms: Vec<String> = fs::read_dir(TEXTS).unwrap()
+            .filter_map(|entry| Some(entry.ok()?.file_name().to_str()?.to_string()))
+            .filter(|room| Path::new(TEXTS).join(room).is_dir())
+            .collect();
+        let dialog = |room: &str, folder: &str, id: i32| Path::new(TEXTS).join(room).join(folder).join(format!("dialog{}-0.txt", id)).exists();
+
+        let mut wanted = BTreeSet::new();
+        for room in &rooms {
+            for (_, _, id) in NPC_KINDS {
+                for faction in Faction::ALL {
+                    for mood in ["friendly", "hostile"] {
+                        let folder = format!("{}_{}", faction.name(), mood);
+                        if dialog(room, &folder, id) {
+                            wanted.insert((room.clone(), folder, id));
+                        }
+                    }
+                }
+            }
+        }
+        assert!(!wanted.is_empty());
+
+        let mut seen = BTreeSet::new();
+        let mut look = |quest: &Quest, step: &str| {
+            for folder in standing_flags(quest) {
+                for placement in placements.iter().filter(|placement| placement.is_present(quest, step)) {
+                    let (_, _, id) = NPC_KINDS.iter().find(|(name, ..)| *name == placement.npc).unwrap();
+                    if !dialog(&placement.room, step, *id) {
+                        seen.insert((placement.room.clone(), folder.clone(), *id));
+                    }
+                }
+            }
+        };
+
+        let mut paths = vec![Quest::new()];
+        for step in STEPS {
+            for quest in paths.iter_mut() {
+                quest.set(step);
+                look(quest, step);
+            }
+
+            // Each choice file at this step splits every path by its options.
+            for room in &rooms {
+                for (_, _, id) in NPC_KINDS {
+                    let choices = match Choice::load_all(Path::new(TEXTS).join(room).join(step).join(format!("choice{}.txt", id))) {
+                        Ok(choices) => choices,
+                        Err(_) => continue,
+                    };
+
+                    let mut picked = Vec::new();
+                    for quest in &paths {
+                        for choice in &choices {
+                            let mut quest = quest.clone();
+                            pick(&mut quest, &shifts, &choice.flag);
+                            look(&quest, step);
+                            picked.push(quest);
+                        }
+                    }
+                    paths = picked;
+                }
+            }
+
+            for dive in dives.iter().filter(|dive| dive.key == step) {
+                for quest in paths.iter_mut() {
+                    shift(quest, Faction::from_name(&dive.owner).unwrap(), -BREACH_COST);
+                }
+            }
+        }
+
+        let unseen: Vec<_> = wanted.difference(&seen).collect();
+        assert!(unseen.is_empty(), "never shown: {:?}", unseen);
+    }
+}
@@ -0,0 +1,119 @@
+// The save file: quest flags and inventory in one text file, each part under
+// its own `[quest]` or `[inventory]` header in the parts' own text forms.
+// The game autosaves to one file and the player can keep a few slots besides.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::inventory::Inventory;
+use crate::quest::Quest;
+
+pub const SAVE_PATH: &str = "saves/save.txt";
+pub const SAVE_SLOTS: usize = 3;
+
+pub fn slot_path(slot: usize) -> String {
+    format!("saves/slot{}.txt", slot + 1)
+}
+
+// The autosave first, then the slots.
+pub fn save_paths() -> Vec<String> {
+    let mut paths = vec![SAVE_PATH.to_string()];
+    paths.extend((0..SAVE_SLOTS).map(slot_path));
+    paths
+}
+
+// The most recently written of the given saves.
+pub fn latest<P: AsRef<Path>>(paths: &[P]) -> Option<&P> {
+    paths.iter()
+        .filter_map(|path| Some((fs::metadata(path).ok()?.modified().ok()?, path)))
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct SaveGame {
+    pub quest: Quest,
+    pub inventory: Inventory,
+}
+
+impl SaveGame {
+    pub fn parse(text: &str) -> Self {
+        let mut quest_text = String::new();
+        let mut inventory_text = String::new();
+
+        let mut section = "";
+        for line in text.lines() {
+            match line.trim() {
+                "[quest]" => section = "quest",
+                "[inventory]" => section = "inventory",
+                line => {
+                    let target = match section {
+                        "quest" => &mut quest_text,
+                        "inventory" => &mut inventory_text,
+                        _ => continue,
+                    };
+                    target.push_str(line);
+                    target.push('\n');
+                },
+            }
+        }
+
+        SaveGame {
+            quest: Quest::parse(&quest_text),
+            inventory: Inventory::parse(&inventory_text),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(SaveGame::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, self.to_text())
+    }
+
+    pub fn to_text(&self) -> String {
+        format!("[quest]\n{}[inventory]\n{}", self.quest.to_text(), self.inventory.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_round_trip() {
+        let mut save = SaveGame::default();
+        save.quest.set("talk");
+        save.quest.add("fragments", 3);
+        save.inventory.give("credits", 120);
+        save.inventory.give("first_key", 1);
+
+        assert_eq!(SaveGame::parse(&save.to_text()), save);
+    }
+
+    #[test]
+    fn the_newest_save_is_latest() {
+        let dir = std::env::temp_dir().join(format!("ld52_saves_{}", std::process::id()));
+        let older = dir.join("slot1.txt");
+        let newer = dir.join("slot2.txt");
+        let missing = dir.join("slot3.txt");
+
+        SaveGame::default().save(&older).unwrap();
+        SaveGame::default().save(&newer).unwrap();
+        let file = fs::File::options().write(true).open(&older).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH).unwrap();
+
+        assert_eq!(latest(&[&older, &missing, &newer]), Some(&&newer));
+        assert_eq!(latest(&[&missing]), None);
+        assert_eq!(save_paths().len(), SAVE_SLOTS + 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+// Move counts and par ratings for finished dives.
+//
+// Best results are kept per dive in a small text file, one `<dive id> <moves>`
+// pair per line, so a replay only replaces the entry when it beats it.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SCORES_PATH: &str = "saves/dive_scores.txt";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Grade {
+    S,
+    A,
+    B,
+    C,
+}
+
+impl Grade {
+    // Par is the solver's minimum, so S means a perfect run.
+    pub fn rate(moves: u32, par: u32) -> Grade {
+        if moves <= par {
+            Grade::S
+        } else if moves * 2 <= par * 3 {
+            Grade::A
+        } else if moves <= par * 2 {
+            Grade::B
+        } else {
+            Grade::C
+        }
+    }
+
+    pub fn letter(self) -> &'static str {
+        match self {
+            Grade::S => "S",
+            Grade::A => "A",
+            Grade::B => "B",
+            Grade::C => "C",
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct DiveScores {
+    best: BTreeMap<String, u32>,
+}
+
+impl DiveScores {
+    pub fn parse(text: &str) -> Self {
+        let mut best = BTreeMap::new();
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            if let (Some(id), Some(moves)) = (parts.next(), parts.next()) {
+                if let Ok(moves) = moves.parse() {
+                    best.insert(id.to_string(), moves);
+                }
+            }
+        }
+
+        DiveScores { best }
+    }
+
+    // A missing or unreadable file just means nothing has been scored yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        fs::read_to_string(path)
+            .map(|text| DiveScores::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, self.to_text())
+    }
+
+    pub fn to_text(&self) -> String {
+        self.best
+            .iter()
+            .map(|(id, moves)| format!("{} {}\n", id, moves))
+            .collect()
+    }
+
+    pub fn best(&self, id: &str) -> Option<u32> {
+        self.best.get(id).copied()
+    }
+
+    // Returns true when the run is a new best for this dive.
+    pub fn record(&mut self, id: &str, moves: u32) -> bool {
+        match self.best.get(id) {
+            Some(&best) if best <= moves => false,
+            _ => {
+                self.best.insert(id.to_string(), moves);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grades_follow_par() {
+        assert_eq!(Grade::rate(8, 8), Grade::S);
+        assert_eq!(Grade::rate(12, 8), Grade::A);
+        assert_eq!(Grade::rate(13, 8), Grade::B);
+        assert_eq!(Grade::rate(17, 8), Grade::C);
+    }
+
+    #[test]
+    fn only_better_runs_replace_the_best() {
+        let mut scores = DiveScores::parse("map0 12\nmap1 9\n");
+
+        assert!(!scores.record("map0", 14));
+        assert!(scores.record("map0", 10));
+        assert!(scores.record("map2", 20));
+
+        assert_eq!(DiveScores::parse(&scores.to_text()).best("map0"), Some(10));
+        assert_eq!(scores.best("map1"), Some(9));
+    }
+}
@@ -0,0 +1,252 @@
+// Player settings, kept in a text file in the user's config folder:
+//
+//     music_volume 0.5
+//     text_speed 1
+//     window_scale 1
+//     fullscreen false
+//     key left Left
+//
+// Text speed is characters typed out per step, 0 for whole lines at once. Every
+// action answers to the game's own key until a `key` line binds another one.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Actions with the keys the game checks for them.
+pub const ACTIONS: [(&str, &str); 11] = [
+    ("left", "A"),
+    ("right", "D"),
+    ("up", "W"),
+    ("down", "S"),
+    ("talk", "Space"),
+    ("undo", "Z"),
+    ("brake", "B"),
+    ("breach", "X"),
+    ("equip", "E"),
+    ("journal", "J"),
+    ("inventory", "I"),
+];
+
+// Keys the menus read as they are, whatever is bound to them.
+pub const MENU_KEYS: [&str; 5] = ["Up", "Down", "Left", "Right", "Space"];
+
+pub const TEXT_SPEEDS: [u32; 4] = [1, 2, 4, 0];
+pub const WINDOW_SCALES: [f32; 3] = [1.0, 1.5, 2.0];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub music_volume: f32,
+    pub text_speed: u32,
+    pub window_scale: f32,
+    pub fullscreen: bool,
+    keys: BTreeMap<String, String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            music_volume: 0.5,
+            text_speed: 1,
+            window_scale: 1.0,
+            fullscreen: false,
+            keys: ACTIONS.iter().map(|(action, key)| (action.to_string(), key.to_string())).collect(),
+        }
+    }
+}
+
+impl Settings {
+    // Unknown or broken lines keep their defaults.
+    pub fn parse(text: &str) -> Self {
+        let mut settings = Settings::default();
+
+        for line in text.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts[..] {
+                ["music_volume", value] => settings.music_volume = value.parse().map_or(settings.music_volume, |volume: f32| volume.clamp(0.0, 1.0)),
+                ["text_speed", value] => settings.text_speed = value.parse().unwrap_or(settings.text_speed),
+                ["window_scale", value] => settings.window_scale = value.parse().map_or(settings.window_scale, |scale: f32| scale.clamp(1.0, 4.0)),
+                ["fullscreen", value] => settings.fullscreen = value == "true",
+                ["key", action, key] => {
+                    settings.bind(action, key);
+                },
+                _ => (),
+            }
+        }
+
+        settings
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Settings::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, self.to_text())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "music_volume {}\ntext_speed {}\nwindow_scale {}\nfullscreen {}\n",
+            self.music_volume, self.text_speed, self.window_scale, self.fullscreen,
+        );
+        for (action, default_key) in ACTIONS {
+            let key = self.key(action);
+            if key != default_key {
+                text.push_str(&format!("key {} {}\n", action, key));
+            }
+        }
+
+        text
+    }
+
+    pub fn key(&self, action: &str) -> &str {
+        self.keys.get(action).map_or("", String::as_str)
+    }
+
+    // An action taking a key that is in use trades keys with its owner.
+    // False, with nothing changed, if that leaves an action that defaults to
+    // a menu key on another one, so one press would work a menu twice.
+    pub fn bind(&mut self, action: &str, key: &str) -> bool {
+        let old_key = match self.keys.get(action) {
+            Some(old_key) => old_key.clone(),
+            None => return false,
+        };
+
+        let mut keys = self.keys.clone();
+        for bound in keys.values_mut() {
+            if bound == key {
+                *bound = old_key.clone();
+            }
+        }
+        keys.insert(action.to_string(), key.to_string());
+
+        let doubled = ACTIONS.iter().any(|(action, default_key)| {
+            let key = keys[*action].as_str();
+            key != *default_key && MENU_KEYS.contains(&key) && MENU_KEYS.contains(default_key)
+        });
+        if !doubled {
+            self.keys = keys;
+        }
+
+        !doubled
+    }
+
+    // A prompt with every `[Key]` naming an action's default key showing the
+    // key bound to that action instead.
+    pub fn show_keys(&self, text: &str) -> String {
+        let mut shown = String::new();
+        let mut rest = text;
+        while let Some(open) = rest.find('[') {
+            let close = match rest[open..].find(']') {
+                Some(close) => open + close,
+                None => break,
+            };
+            let name = &rest[open + 1..close];
+            let key = ACTIONS.iter().find(|(_, default_key)| *default_key == name).map_or(name, |(action, _)| self.key(action));
+            shown.push_str(&rest[..open]);
+            shown.push_str(&format!("[{}]", key));
+            rest = &rest[close + 1..];
+        }
+        shown.push_str(rest);
+
+        shown
+    }
+
+    pub fn has_default_keys(&self) -> bool {
+        ACTIONS.iter().all(|(action, key)| self.key(action) == *key)
+    }
+
+    // The keys the game should see for a pressed one: the default key of the
+    // action bound to it, and the key itself unless it is some action's default.
+    pub fn game_keys(&self, key: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        if ACTIONS.iter().all(|(_, default_key)| *default_key != key) {
+            keys.push(key.to_string());
+        }
+        for (action, default_key) in ACTIONS {
+            if self.key(action) == key {
+                keys.push(default_key.to_string());
+            }
+        }
+
+        keys
+    }
+}
+
+// `$XDG_CONFIG_HOME`, `%APPDATA%` or the usual folder under the home directory.
+pub fn config_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| {
+            let home = PathBuf::from(env::var_os("HOME")?);
+            Some(if cfg!(target_os = "macos") { home.join("Library/Application Support") } else { home.join(".config") })
+        })?;
+
+    Some(dir.join("cyberharvest").join("settings.txt"))
+}
+
+// How much of a line is shown after one more step of typing it out.
+pub fn typed_len(shown: usize, full: usize, text_speed: u32) -> usize {
+    if text_speed == 0 {
+        full
+    } else {
+        (shown + text_speed as usize).min(full)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip_and_keep_defaults() {
+        let mut settings = Settings::parse("music_volume 2\ntext_speed 0\nbogus line\nkey nothing Q\n");
+        assert_eq!(settings.music_volume, 1.0);
+        assert_eq!(settings.text_speed, 0);
+        assert!(settings.has_default_keys());
+
+        settings.fullscreen = true;
+        settings.bind("left", "Left");
+        assert_eq!(Settings::parse(&settings.to_text()), settings);
+        assert_eq!(typed_len(3, 10, 4), 7);
+        assert_eq!(typed_len(3, 10, 0), 10);
+    }
+
+    #[test]
+    fn bound_keys_stand_in_for_the_defaults() {
+        let mut settings = Settings::default();
+        settings.bind("left", "D");
+
+        assert_eq!(settings.key("right"), "A");
+        assert_eq!(settings.game_keys("D"), vec!["A"]);
+        assert_eq!(settings.game_keys("A"), vec!["D"]);
+        assert_eq!(settings.game_keys("Escape"), vec!["Escape"]);
+
+        settings.bind("talk", "Return");
+        assert_eq!(settings.game_keys("Return"), vec!["Return", "Space"]);
+        assert!(settings.game_keys("Space").is_empty());
+
+        assert_eq!(settings.show_keys("[A] left, [D] right, [Space] talk, [Esc] [W"), "[D] left, [A] right, [Return] talk, [Esc] [W");
+    }
+
+    #[test]
+    fn menu_keys_only_work_menus_once() {
+        let mut settings = Settings::default();
+        assert!(settings.bind("up", "Up"));
+        assert!(!settings.bind("talk", "Down"));
+        // Taking Space would hand talk the Up key in the trade.
+        assert!(!settings.bind("up", "Space"));
+        assert_eq!(settings.key("talk"), "Space");
+
+        assert!(Settings::parse("key talk Left\n").has_default_keys());
+    }
+}
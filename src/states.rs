@@ -1,22 +1,87 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
+use std::path::Path;
 
+use bevy::app::AppExit;
 use bevy::{
+    audio::AudioSink,
+    ecs::{schedule::ShouldRun, system::SystemParam},
+    input::InputSystem,
     prelude::*,
     sprite::collide_aabb::{collide, Collision},
     time::FixedTimestep,
+    window::WindowMode,
 };
 
+use ld52::cyberware::{self, Cyberware, Rig, CYBERWARE_SLOTS};
+use ld52::dive::{self, Direction, DiveMap, DiveSet, Exit, Position, Step, Tile, DIVE_SETS_PATH, MAP_DIR};
+use ld52::generator::{self, Difficulty};
+use ld52::inventory::{self, Inventory, CREDITS, ITEM_NAMES_PATH, STOCK_PATH};
+use ld52::journal::{Journal, JOURNAL_PATH};
+use ld52::npc::{self, Placement, NPCS_PATH, NPC_KINDS};
+use ld52::quest::Quest;
+use ld52::replay::{DiveEnd, DiveInput, Replay, REPLAY_DIR};
+use ld52::reputation::{self, Faction, Shift, BREACH_COST, REPUTATION_PATH};
+use ld52::save::{self, SaveGame, SAVE_PATH, SAVE_SLOTS};
+use ld52::scores::{DiveScores, Grade, SCORES_PATH};
+use ld52::settings::{self, Settings, ACTIONS, TEXT_SPEEDS, WINDOW_SCALES, typed_len};
+use ld52::story::{self, Choice, Ending, ENDINGS_PATH};
+
 const TIME_STEP: f32 = 1.0 / 60.0;
 
+// The size everything is laid out for; the window scale setting multiplies it.
+const WINDOW_WIDTH: f32 = 1280.0;
+const WINDOW_HEIGHT: f32 = 720.0;
+
 const DEEP_DIVE_TILE_SCALE: f32 = 64.0;
 
+const DIVE_TILESET_SIZE: f32 = 16.0;
+
 const PLAYER_SPEED: f32 = 6.0;
 
 const BORDER_MARGIN: f32 = 7.0;
 
+// Extra seeds tried when the generator gives up on a level's own.
+const GENERATOR_RETRIES: u64 = 8;
+
+// Frames between moves when playing a replay back, so it can be followed.
+const REPLAY_MOVE_DELAY: u32 = 20;
+
+// Slides that can be undone in a dive, indexed by data bank. Zero disables
+// undo, so the later heists can be made unforgiving.
+const DIVE_UNDO_LIMITS: [usize; 3] = [10, 5, 0];
+
+// Seconds an idle diver lasts before security completes the trace, indexed by
+// data bank, and the share of the meter every slide or undo adds on top.
+const DIVE_TRACE_SECONDS: [f32; 3] = [150.0, 120.0, 90.0];
+const DIVE_TRACE_PER_MOVE: f32 = 0.015;
+
+// Frames the diver flashes frozen on the lava, then grows back in at the spawn.
+const DIVE_DEATH_FRAMES: u32 = 45;
+const DIVE_RESPAWN_FRAMES: u32 = 20;
+
+// Credits paid for a dive by grade (S, A, B, C), plus a bonus per fragment.
+const DIVE_REWARDS: [u32; 4] = [100, 75, 50, 30];
+const FRAGMENT_REWARD: u32 = 10;
+
+// Quest counter with the fragments of every bank's best dive added up.
+const FRAGMENTS_FOUND: &str = "fragments_found";
+// Quest counter with the fragments the last lore entry told needed.
+const LORE_TOLD: &str = "lore_told";
+
+// Paid by the Lycia Cafe barista once Pip has the coffee.
+const COFFEE_REWARD: u32 = 60;
+
+// Side quest stages in order. The furthest stage set in each chain can give
+// NPCs their own dialog folder.
+const DIALOG_FLAG_CHAINS: [&[&str]; 1] = [
+    &["coffee_offered", "coffee_delivered", "coffee_paid"],
+];
+
 pub struct StatesPlugin;
 
 enum State {
+    Title,
     Intro,
     End,
     Helionix,
@@ -29,6 +94,7 @@ enum State {
     Pod,
     Alleyway2,
     DeepDive,
+    Editor,
 }
 
 #[derive(Clone, PartialEq)]
@@ -43,36 +109,65 @@ enum GameProgress {
     GetFinalData,
 }
 
+const STORY: [GameProgress; 8] = [
+    GameProgress::Start,
+    GameProgress::TalkToFigure,
+    GameProgress::GetFirstKey,
+    GameProgress::GetFirstData,
+    GameProgress::GetSecondKey,
+    GameProgress::GetSecondData,
+    GameProgress::GetFinalKey,
+    GameProgress::GetFinalData,
+];
+
+impl GameProgress {
+    // The main story on top of the quest flags: the furthest step whose flag
+    // (see `progress_key`) is set.
+    fn of(quest: &Quest) -> GameProgress {
+        STORY.iter().rev()
+            .find(|step| quest.has(progress_key(step)))
+            .cloned()
+            .unwrap_or(GameProgress::Start)
+    }
+}
+
+const TITLE_OPTIONS: [&str; 5] = ["New Game", "Continue", "Load", "Settings", "Quit"];
+
+struct TitleState {
+    menu_entity: Option<Entity>,
+    menu_index: usize,
+    menu_msg: String,
+    // The save list is open instead of the main options.
+    loading: bool,
+    opening_settings: bool,
+    quitting: bool,
+}
+
 struct IntroState {
-    check_progress: GameProgress,
     current_text: String,
     story_texts: Vec<String>,
     current_story_line: usize,
 }
 
 struct EndState {
-    check_progress: GameProgress,
     current_text: String,
     story_texts: Vec<String>,
     current_story_line: usize,
 }
 
 struct HelionixState {
-    check_progress: GameProgress,
     current_text: String,
     story_texts: Vec<String>,
     current_story_line: usize,
 }
 
 struct FusiogenicState {
-    check_progress: GameProgress,
     current_text: String,
     story_texts: Vec<String>,
     current_story_line: usize,
 }
 
 struct TechShopState {
-    check_progress: GameProgress,
     talking_entity: Entity,
     current_talking: u32,
     has_played_msg: bool,
@@ -82,6 +177,7 @@ struct TechShopState {
     dialog_line: usize,
     dialog_state: u32,
     dialog_texts: Vec<String>,
+    choice_menu: ChoiceMenu,
 
     location_string: String,
     alleyway_door: String,
@@ -92,7 +188,6 @@ struct TechShopState {
 }
 
 struct AlleywayState {
-    check_progress: GameProgress,
     talking_entity: Entity,
     current_talking: u32,
     has_played_msg: bool,
@@ -112,7 +207,6 @@ struct AlleywayState {
 }
 
 struct CyberwayState {
-    check_progress: GameProgress,
     talking_entity: Entity,
     current_talking: u32,
     has_played_msg: bool,
@@ -134,7 +228,6 @@ struct CyberwayState {
 }
 
 struct PartsShop {
-    check_progress: GameProgress,
     talking_entity: Entity,
     current_talking: u32,
     has_played_msg: bool,
@@ -147,12 +240,18 @@ struct PartsShop {
 
     location_string: String,
     cyberway_door: String,
+    ask_shopkeeper: String,
+
+    stock: Vec<(String, u32)>,
+    item_names: BTreeMap<String, String>,
+    menu_entity: Option<Entity>,
+    menu_index: usize,
+    menu_msg: String,
 
     bounds: Vec2,
 }
 
 struct CafeState {
-    check_progress: GameProgress,
     talking_entity: Entity,
     current_talking: u32,
     has_played_msg: bool,
@@ -160,8 +259,10 @@ struct CafeState {
     current_msg: String,
     talking: bool,
     dialog_line: usize,
-    dialog_state: u32,
+    // How far each NPC got with the lines of each dialog folder.
+    dialog_states: BTreeMap<(u32, String), u32>,
     dialog_texts: Vec<String>,
+    choice_menu: ChoiceMenu,
 
     location_string: String,
     cyberway_door: String,
@@ -172,7 +273,6 @@ struct CafeState {
 }
 
 struct PodState {
-    check_progress: GameProgress,
     talking_entity: Entity,
     current_talking: u32,
     has_played_msg: bool,
@@ -186,12 +286,17 @@ struct PodState {
     location_string: String,
     cafe_door: String,
     enter_pod: String,
+    dive_result: Option<String>,
+    dev_mode: bool,
+    item_names: BTreeMap<String, String>,
+    menu_entity: Option<Entity>,
+    menu_index: usize,
+    menu_msg: String,
 
     bounds: Vec2,
 }
 
 struct Alleyway2State {
-    check_progress: GameProgress,
     talking_entity: Entity,
     current_talking: u32,
     has_played_msg: bool,
@@ -201,6 +306,7 @@ struct Alleyway2State {
     dialog_line: usize,
     dialog_state: u32,
     dialog_texts: Vec<String>,
+    choice_menu: ChoiceMenu,
 
     location_string: String,
     ask_figure: String,
@@ -209,15 +315,90 @@ struct Alleyway2State {
     bounds: Vec2,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum EditorBrush {
+    Tile(Tile),
+    Spawn,
+}
+
+struct EditorState {
+    map: DiveMap,
+    files: Vec<String>,
+    file_index: usize,
+    cursor: Position,
+    brush: EditorBrush,
+    camera: Vec2,
+    last_mouse: Option<Vec2>,
+    redraw: bool,
+    solver_status: String,
+    message: String,
+}
+
+// Where [Z] goes back to: the spot a slide started from, with the parts'
+// charges and the walls and fragments as they were before it.
+struct DiveUndo {
+    position: Position,
+    rig: Rig,
+    broken: Vec<Position>,
+    collected: Vec<Position>,
+}
+
 struct DeepDiveState {
-    check_progress: GameProgress,
-    level: u32
+    level: u32,
+    map: DiveMap,
+    position: Position,
+    sliding: Option<Direction>,
+    seed: Option<u64>,
+    history: Vec<DiveUndo>,
+    undo_limit: usize,
+    bank: u32,
+    recording: Vec<DiveInput>,
+    playback: VecDeque<DiveInput>,
+    playback_delay: u32,
+    // Watching a replay. The keyboard plays no part in it, and once the
+    // inputs run out the dive ends the way the recorded run did.
+    replaying: bool,
+    replay_end: Option<DiveEnd>,
+    test_map: Option<DiveMap>,
+    dive_set: DiveSet,
+    trace: f32,
+    trace_rate: f32,
+    traced_out: bool,
+    level_deaths: Vec<u32>,
+    level_fragments: Vec<u32>,
+    collected: Vec<Position>,
+    fragments_on_level: u32,
+    dying: u32,
+    respawning: u32,
+    rig: Rig,
+    // The rig as the level was entered, for restarts.
+    level_rig: Rig,
+    broken: Vec<Position>,
+    abandoned: bool,
+
+    dive_id: String,
+    level_par: u32,
+    level_moves: u32,
+    completed_par: u32,
+    dive_moves: u32,
+}
+
+impl TitleState {
+    fn new() -> Self {
+        TitleState {
+            menu_entity: None,
+            menu_index: 0,
+            menu_msg: String::from(""),
+            loading: false,
+            opening_settings: false,
+            quitting: false,
+        }
+    }
 }
 
 impl IntroState {
     fn new() -> Self {
         IntroState {
-            check_progress: GameProgress::Start,
             current_text: String::from(""),
             story_texts: Vec::new(),
             current_story_line: 0,
@@ -228,7 +409,6 @@ impl IntroState {
 impl EndState {
     fn new() -> Self {
         EndState {
-            check_progress: GameProgress::Start,
             current_text: String::from(""),
             story_texts: Vec::new(),
             current_story_line: 0,
@@ -239,7 +419,6 @@ impl EndState {
 impl HelionixState {
     fn new() -> Self {
         HelionixState {
-            check_progress: GameProgress::Start,
             current_text: String::from(""),
             story_texts: Vec::new(),
             current_story_line: 0,
@@ -250,7 +429,6 @@ impl HelionixState {
 impl FusiogenicState {
     fn new() -> Self {
         FusiogenicState {
-            check_progress: GameProgress::Start,
             current_text: String::from(""),
             story_texts: Vec::new(),
             current_story_line: 0,
@@ -261,7 +439,6 @@ impl FusiogenicState {
 impl TechShopState {
     fn new() -> Self {
         TechShopState {
-            check_progress: GameProgress::Start,
             talking_entity: Entity::from_raw(0),
             current_talking: 0,
             has_played_msg: false,
@@ -271,6 +448,7 @@ impl TechShopState {
             dialog_line: 0,
             dialog_state: 0,
             dialog_texts: Vec::new(),
+            choice_menu: ChoiceMenu::default(),
 
             location_string: String::from("Location: Tech Workshop."),
             alleyway_door: String::from("Press [W] to enter the alleyway."),
@@ -285,7 +463,6 @@ impl TechShopState {
 impl AlleywayState {
     fn new() -> Self {
         AlleywayState {
-            check_progress: GameProgress::Start,
             talking_entity: Entity::from_raw(0),
             current_talking: 0,
             has_played_msg: false,
@@ -309,7 +486,6 @@ impl AlleywayState {
 impl CyberwayState {
     fn new() -> Self {
         CyberwayState {
-            check_progress: GameProgress::Start,
             talking_entity: Entity::from_raw(0),
             current_talking: 0,
             has_played_msg: false,
@@ -335,7 +511,6 @@ impl CyberwayState {
 impl PartsShop {
     fn new() -> Self {
         PartsShop {
-            check_progress: GameProgress::Start,
             talking_entity: Entity::from_raw(0),
             current_talking: 0,
             has_played_msg: false,
@@ -349,6 +524,14 @@ impl PartsShop {
             location_string: String::from("Location: Cyber Parts Shop."),
             cyberway_door: String::from("Press [W] to enter the Cyberway."),
 
+            ask_shopkeeper: String::from("Shopkeeper: Press [Space] to shop."),
+
+            stock: Vec::new(),
+            item_names: BTreeMap::new(),
+            menu_entity: None,
+            menu_index: 0,
+            menu_msg: String::from(""),
+
             bounds: Vec2::new(-275.0, 275.0),
         }
     }
@@ -357,7 +540,6 @@ impl PartsShop {
 impl CafeState {
     fn new() -> Self {
         CafeState {
-            check_progress: GameProgress::Start,
             talking_entity: Entity::from_raw(0),
             current_talking: 0,
             has_played_msg: false,
@@ -365,8 +547,9 @@ impl CafeState {
             current_msg: String::from(""),
             talking: false,
             dialog_line: 0,
-            dialog_state: 0,
+            dialog_states: BTreeMap::new(),
             dialog_texts: Vec::new(),
+            choice_menu: ChoiceMenu::default(),
 
             location_string: String::from("Location: Lycia Cafe."),
             cyberway_door: String::from("Press [W] to enter the Cyberway."),
@@ -381,7 +564,6 @@ impl CafeState {
 impl PodState {
     fn new() -> Self {
         PodState {
-            check_progress: GameProgress::Start,
             talking_entity: Entity::from_raw(0),
             current_talking: 0,
             has_played_msg: false,
@@ -392,11 +574,17 @@ impl PodState {
             dialog_state: 0,
             dialog_texts: Vec::new(),
 
-            location_string: String::from("Location: Deep Dive Pod Room."),
+            location_string: String::from("Location: Deep Dive Pod Room. [E] Cyberware."),
 
             bounds: Vec2::new(-280.0, 280.0),
             cafe_door: String::from("Press [W] to enter Lycia Cafe."),
             enter_pod: String::from("Press [W] to Deep Dive."),
+            dive_result: None,
+            dev_mode: false,
+            item_names: BTreeMap::new(),
+            menu_entity: None,
+            menu_index: 0,
+            menu_msg: String::from(""),
         }
     }
 }
@@ -404,7 +592,6 @@ impl PodState {
 impl Alleyway2State {
     fn new() -> Self {
         Alleyway2State {
-            check_progress: GameProgress::Start,
             talking_entity: Entity::from_raw(0),
             current_talking: 0,
             has_played_msg: false,
@@ -414,6 +601,7 @@ impl Alleyway2State {
             dialog_line: 0,
             dialog_state: 0,
             dialog_texts: Vec::new(),
+            choice_menu: ChoiceMenu::default(),
 
             location_string: String::from("Location: Lycia Cafe Back Alley."),
             ask_figure: String::from("Mysterious Figure: Press [Space] to talk."),
@@ -427,14 +615,292 @@ impl Alleyway2State {
 impl DeepDiveState {
     fn new() -> Self {
         DeepDiveState {
-            check_progress: GameProgress::Start,
-            level: 0
+            level: 0,
+            map: DiveMap::parse(""),
+            position: (0, 0),
+            sliding: None,
+            seed: None,
+            history: Vec::new(),
+            undo_limit: 0,
+            bank: 0,
+            recording: Vec::new(),
+            playback: VecDeque::new(),
+            playback_delay: 0,
+            replaying: false,
+            replay_end: None,
+            test_map: None,
+            dive_set: DiveSet { key: String::from(""), owner: String::from(""), levels: Vec::new() },
+            trace: 0.0,
+            trace_rate: 0.0,
+            traced_out: false,
+            level_deaths: Vec::new(),
+            level_fragments: Vec::new(),
+            collected: Vec::new(),
+            fragments_on_level: 0,
+            dying: 0,
+            respawning: 0,
+            rig: Rig::default(),
+            level_rig: Rig::default(),
+            broken: Vec::new(),
+            abandoned: false,
+
+            dive_id: String::from(""),
+            level_par: 0,
+            level_moves: 0,
+            completed_par: 0,
+            dive_moves: 0,
+        }
+    }
+}
+
+impl EditorState {
+    fn new() -> Self {
+        EditorState {
+            map: DiveMap::new(0, 0),
+            files: Vec::new(),
+            file_index: 0,
+            cursor: (0, 0),
+            brush: EditorBrush::Tile(Tile::Wall),
+            camera: Vec2::ZERO,
+            last_mouse: None,
+            redraw: true,
+            solver_status: String::from(""),
+            message: String::from(""),
+        }
+    }
+}
+
+// What happened on one dive, kept with the progress for the results screen.
+#[derive(Clone, Default)]
+struct DiveStats {
+    bank: u32,
+    moves: u32,
+    level_deaths: Vec<u32>,
+    level_fragments: Vec<u32>,
+    traced_out: bool,
+}
+
+impl DiveStats {
+    fn deaths(&self) -> u32 {
+        self.level_deaths.iter().sum()
+    }
+
+    fn fragments(&self) -> u32 {
+        self.level_fragments.iter().sum()
+    }
+
+    // Adds the dive to the bank's counters in the quest, so every save carries
+    // them: tries, traced tries and the fragments of its best try that got out.
+    fn record(&self, quest: &mut Quest) {
+        quest.add(&format!("dive_tries_{}", self.bank), 1);
+        if self.traced_out {
+            quest.add(&format!("dive_traced_{}", self.bank), 1);
+            return;
+        }
+
+        // Fragments only count from dives that got out with the data, and a
+        // bank's fragments once, from its best try.
+        let best = format!("dive_fragments_{}", self.bank);
+        let gained = self.fragments() as i64 - quest.count(&best);
+        if gained > 0 {
+            quest.add(&best, gained);
+            quest.add(FRAGMENTS_FOUND, gained);
+        }
+    }
+}
+
+// Results line for the latest dive, counted against earlier tries on its bank.
+fn dive_record(quest: &Quest, last: &DiveStats) -> String {
+    let tries = quest.count(&format!("dive_tries_{}", last.bank));
+    let traced = quest.count(&format!("dive_traced_{}", last.bank));
+    let level_deaths: Vec<String> = last.level_deaths.iter().map(|deaths| deaths.to_string()).collect();
+
+    let mut record = format!("Try {} on this bank ({} traced), {} moves.", tries, traced, last.moves);
+    if !level_deaths.is_empty() {
+        record.push_str(&format!(" Deaths per level: {}.", level_deaths.join("/")));
+    }
+    if last.fragments() > 0 && !last.traced_out {
+        record.push_str(&format!(" Data fragments: {} ({} found in all).", last.fragments(), quest.count(FRAGMENTS_FOUND)));
+    }
+
+    record
+}
+
+impl PauseMenu {
+    fn close(&mut self, commands: &mut Commands) {
+        if let Some(entity) = self.entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+        self.open = false;
+    }
+
+    fn redraw(&mut self, commands: &mut Commands, asset_server: &Res<AssetServer>, settings: &Settings, options: &[&str]) {
+        if let Some(entity) = self.entity.take() {
+            commands.entity(entity).despawn_recursive();
         }
+
+        let mut menu_text = String::new();
+        if self.saving {
+            menu_text.push_str("SAVE GAME\n\n");
+            for slot in 0..SAVE_SLOTS {
+                let cursor = if slot == self.index { ">" } else { " " };
+                menu_text.push_str(&format!("{} Slot {}  {}\n", cursor, slot + 1, save_summary(&save::slot_path(slot))));
+            }
+            menu_text.push_str(&format!("\n{}\n\n[Up/Down] Choose  [Space] Save  [Esc] Back", self.msg));
+        } else {
+            menu_text.push_str("PAUSED\n\n");
+            for (index, option) in options.iter().enumerate() {
+                let cursor = if index == self.index { ">" } else { " " };
+                menu_text.push_str(&format!("{} {}\n", cursor, option));
+            }
+            menu_text.push_str(&format!("\n{}\n\n[Up/Down] Choose  [Space] Select  [Esc] Resume", self.msg));
+        }
+
+        self.entity = Some(spawn_menu(commands, asset_server, settings, menu_text));
+    }
+}
+
+impl SettingsMenu {
+    fn open(&mut self, commands: &mut Commands, asset_server: &Res<AssetServer>, settings: &Settings) {
+        self.open = true;
+        self.index = 0;
+        self.binding = None;
+        self.redraw(commands, asset_server, settings);
+    }
+
+    fn close(&mut self, commands: &mut Commands, settings: &Settings) {
+        if let Some(entity) = self.entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+        self.open = false;
+
+        if let Some(path) = settings::config_path() {
+            if let Err(e) = settings.save(&path) {
+                warn!("Cannot save settings {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn adjust(&self, settings: &mut Settings, step: i32) {
+        // Volumes go in tenths, kept round so the file stays readable.
+        let volume = |volume: f32| ((volume * 10.0).round() + step as f32).clamp(0.0, 10.0) / 10.0;
+
+        match self.index {
+            0 => settings.music_volume = volume(settings.music_volume),
+            1 => settings.text_speed = cycle(&TEXT_SPEEDS, settings.text_speed, step),
+            2 => settings.window_scale = cycle(&WINDOW_SCALES, settings.window_scale, step),
+            3 => settings.fullscreen = !settings.fullscreen,
+            _ => (),
+        }
+    }
+
+    fn redraw(&mut self, commands: &mut Commands, asset_server: &Res<AssetServer>, settings: &Settings) {
+        if let Some(entity) = self.entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        let text_speed = match settings.text_speed {
+            0 => String::from("Instant"),
+            1 => String::from("Normal"),
+            2 => String::from("Fast"),
+            speed => format!("{} per frame", speed),
+        };
+        let mut rows = vec![
+            (String::from("Music volume"), format!("< {:.0}% >", settings.music_volume * 100.0)),
+            (String::from("Text speed"), format!("< {} >", text_speed)),
+            (String::from("Window scale"), format!("< {}x >", settings.window_scale)),
+            (String::from("Fullscreen"), String::from(if settings.fullscreen { "On" } else { "Off" })),
+        ];
+        for (index, (action, _)) in ACTIONS.iter().enumerate() {
+            let key = if self.binding == Some(index) { "press a key" } else { settings.key(action) };
+            rows.push((format!("Key: {}", action), key.to_string()));
+        }
+        rows.push((String::from("Back"), String::new()));
+
+        let mut menu_text = String::from("SETTINGS\n\n");
+        for (index, (name, value)) in rows.iter().enumerate() {
+            let cursor = if index == self.index { ">" } else { " " };
+            menu_text.push_str(&format!("{} {:<16} {}\n", cursor, name, value));
+        }
+        if self.binding.is_some() {
+            menu_text.push_str(&format!("\n{}\n\n[Esc] Keep the old key", self.msg));
+        } else {
+            menu_text.push_str("\n[Up/Down] Choose  [Left/Right] Change  [Space] Select  [Esc] Back");
+        }
+
+        self.entity = Some(spawn_menu(commands, asset_server, settings, menu_text));
+    }
+}
+
+// The option before or after the current one, wrapping around.
+fn cycle<T: PartialEq + Copy>(options: &[T], current: T, step: i32) -> T {
+    let index = options.iter().position(|option| *option == current).unwrap_or(0) as i32;
+    options[(index + step).rem_euclid(options.len() as i32) as usize]
+}
+
+// Options offered at the end of a talk, from a choice file next to the dialog.
+#[derive(Default)]
+struct ChoiceMenu {
+    choices: Vec<Choice>,
+    entity: Option<Entity>,
+    index: usize,
+}
+
+impl ChoiceMenu {
+    fn is_open(&self) -> bool {
+        self.entity.is_some()
+    }
+
+    fn open(&mut self, commands: &mut Commands, asset_server: &Res<AssetServer>, settings: &Settings, choices: Vec<Choice>) {
+        self.choices = choices;
+        self.index = 0;
+        self.redraw(commands, asset_server, settings);
+    }
+
+    // The flag of the picked option. There is no backing out of a choice.
+    fn run(
+        &mut self,
+        commands: &mut Commands,
+        asset_server: &Res<AssetServer>,
+        settings: &Settings,
+        keyboard_input: &Res<Input<KeyCode>>,
+    ) -> Option<String> {
+        if keyboard_input.just_pressed(KeyCode::Up) {
+            self.index = (self.index + self.choices.len() - 1) % self.choices.len();
+        } else if keyboard_input.just_pressed(KeyCode::Down) {
+            self.index = (self.index + 1) % self.choices.len();
+        } else if keyboard_input.just_pressed(KeyCode::Space) {
+            if let Some(entity) = self.entity.take() {
+                commands.entity(entity).despawn_recursive();
+            }
+            return Some(self.choices[self.index].flag.clone());
+        } else {
+            return None;
+        }
+
+        self.redraw(commands, asset_server, settings);
+        None
+    }
+
+    fn redraw(&mut self, commands: &mut Commands, asset_server: &Res<AssetServer>, settings: &Settings) {
+        if let Some(entity) = self.entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        let mut menu_text = String::from("What do you do?\n\n");
+        for (index, choice) in self.choices.iter().enumerate() {
+            let cursor = if index == self.index { ">" } else { " " };
+            menu_text.push_str(&format!("{} {}\n", cursor, choice.text));
+        }
+        menu_text.push_str("\n[Up/Down] Choose  [Space] Decide");
+
+        self.entity = Some(spawn_menu(commands, asset_server, settings, menu_text));
     }
 }
 
 struct StateCollection {
-    game_progress: GameProgress,
+    quest: Quest,
+    title_state: TitleState,
     intro_state: IntroState,
     end_state: EndState,
     helionix_state: HelionixState,
@@ -447,12 +913,14 @@ struct StateCollection {
     pod_state: PodState,
     alleyway2_state: Alleyway2State,
     deep_dive_state: DeepDiveState,
+    editor_state: EditorState,
 }
 
 impl StateCollection {
     fn new() -> Self {
         StateCollection {
-            game_progress: GameProgress::Start,
+            quest: Quest::new(),
+            title_state: TitleState::new(),
             intro_state: IntroState::new(),
             end_state: EndState::new(),
             helionix_state: HelionixState::new(),
@@ -465,6 +933,30 @@ impl StateCollection {
             pod_state: PodState::new(),
             alleyway2_state: Alleyway2State::new(),
             deep_dive_state: DeepDiveState::new(),
+            editor_state: EditorState::new(),
+        }
+    }
+
+    // Fresh rooms for a new or loaded game, keeping its quest and the command
+    // line options.
+    fn restart(&mut self) {
+        let mut fresh = StateCollection::new();
+        fresh.quest = std::mem::take(&mut self.quest);
+        fresh.deep_dive_state.seed = self.deep_dive_state.seed;
+        fresh.pod_state.dev_mode = self.pod_state.dev_mode;
+        *self = fresh;
+    }
+
+    // A room menu or test play has [Esc] for itself.
+    fn has_escape(&self, state: &State) -> bool {
+        match state {
+            State::TechShop => self.tech_shop_state.choice_menu.is_open(),
+            State::Cafe => self.cafe_state.choice_menu.is_open(),
+            State::Alleyway2 => self.alleyway2_state.choice_menu.is_open(),
+            State::PartsShop => self.parts_shop_state.menu_entity.is_some(),
+            State::Pod => self.pod_state.menu_entity.is_some(),
+            State::DeepDive => self.deep_dive_state.test_map.is_some(),
+            _ => false,
         }
     }
 }
@@ -479,21 +971,22 @@ struct AnimationMode(u32);
 struct StoryText;
 
 #[derive(Component)]
-struct Player {
-    velocity: Vec2
-}
+struct DiveTile;
 
 #[derive(Component)]
-struct Collider;
+struct EditorCursor;
 
 #[derive(Component)]
-struct Lava;
+struct DiveTileAt(Position);
 
+// A journal or inventory overlay, with the key that opened it.
 #[derive(Component)]
-struct DataPort;
+struct Overlay(KeyCode);
 
 #[derive(Component)]
-struct Portal;
+struct Player {
+    velocity: Vec2
+}
 
 #[derive(Component)]
 struct NPC {
@@ -509,41 +1002,338 @@ struct NextState(State);
 #[derive(Resource)]
 struct StateData(StateCollection);
 
+#[derive(Resource)]
+struct PlayerInventory(Inventory);
+
+const HUB_PAUSE_OPTIONS: [&str; 4] = ["Resume", "Save", "Settings", "Quit to Title"];
+// The quest only moves on once a dive is over, so a save made in one keeps
+// the game as it was before it: loading it goes back to the pod, key in hand.
+const DIVE_PAUSE_OPTIONS: [&str; 6] = ["Resume", "Save", "Restart Level", "Abandon Dive", "Settings", "Quit to Title"];
+// A replay has no game behind it to save and nothing to abandon.
+const REPLAY_PAUSE_OPTIONS: [&str; 3] = ["Resume", "Settings", "Quit to Title"];
+
+#[derive(Resource, Default)]
+struct PauseMenu {
+    open: bool,
+    entity: Option<Entity>,
+    index: usize,
+    msg: String,
+    // The slot list is open instead of the main options.
+    saving: bool,
+}
+
+#[derive(Resource)]
+struct GameSettings(Settings);
+
+// The keyboard as it really is, while the game reads it with the bindings applied.
+#[derive(Resource, Default)]
+struct PhysicalKeys(Input<KeyCode>);
+
+// The track playing, so a volume change reaches it right away.
+#[derive(Resource, Default)]
+struct Music(Option<Handle<AudioSink>>);
+
+// What every screen draws and plays its music with.
+#[derive(SystemParam)]
+struct SharedResources<'w, 's> {
+    commands: Commands<'w, 's>,
+    asset_server: Res<'w, AssetServer>,
+    texture_atlases: ResMut<'w, Assets<TextureAtlas>>,
+    audio: Res<'w, Audio>,
+    music: ResMut<'w, Music>,
+    settings: ResMut<'w, GameSettings>,
+}
+
+// The player, the message line and the NPCs of a room.
+#[derive(SystemParam)]
+struct RoomQueries<'w, 's> {
+    player_query: Query<'w, 's, (&'static mut TextureAtlasSprite, &'static mut AnimationTimer, &'static mut Transform, &'static mut Player), With<Player>>,
+    story_query: Query<'w, 's, &'static mut Text, With<StoryText>>,
+    npc_query: Query<'w, 's, (&'static NPC, &'static Transform), (Without<Player>, With<NPC>)>,
+}
+
+// The mouse and the editor's own entities.
+#[derive(SystemParam)]
+struct EditorInput<'w, 's> {
+    mouse_input: Res<'w, Input<MouseButton>>,
+    windows: Res<'w, Windows>,
+    cursor_query: Query<'w, 's, &'static mut Transform, (With<EditorCursor>, Without<Player>, Without<NPC>)>,
+    tile_query: Query<'w, 's, Entity, With<DiveTile>>,
+}
+
+// Music volume, text speed, window and fullscreen, then the key bindings.
+const SETTINGS_VALUES: usize = 4;
+const SETTINGS_ROWS: usize = SETTINGS_VALUES + ACTIONS.len() + 1;
+
+// Keys an action can be bound to. [Esc] stays with the menus.
+const BINDABLE_KEYS: [KeyCode; 48] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Space, KeyCode::Return, KeyCode::Tab, KeyCode::LShift, KeyCode::RShift,
+    KeyCode::LControl, KeyCode::RControl, KeyCode::LAlt,
+    KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right,
+];
+
+#[derive(Resource, Default)]
+struct SettingsMenu {
+    open: bool,
+    entity: Option<Entity>,
+    index: usize,
+    // The action waiting for a key press.
+    binding: Option<usize>,
+    msg: String,
+}
+
+// The dive being played, picked from the progress step by `dive_bank`.
 #[derive(Resource)]
 struct DeepDiveDataBank(u32);
 
 impl Plugin for StatesPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(CurrentState(State::Intro))
-        .insert_resource(NextState(State::Intro))
-        .insert_resource(StateData(StateCollection::new()))
-        .insert_resource(DeepDiveDataBank(0))
+        let mut state_collection = StateCollection::new();
+        state_collection.deep_dive_state.seed = dive_seed_from_args();
+        state_collection.pod_state.dev_mode = std::env::args().any(|arg| arg == "--dev");
+
+        let mut initial_state = State::Title;
+        let mut initial_bank = 0;
+
+        if let Some(replay) = replay_from_args() {
+            let bank = replay.bank;
+            if state_collection.deep_dive_state.begin_playback(replay) {
+                initial_state = State::DeepDive;
+                initial_bank = bank;
+            }
+        }
+
+        app.insert_resource(CurrentState(initial_state))
+        .insert_resource(NextState(State::Title))
+        .insert_resource(StateData(state_collection))
+        .insert_resource(DeepDiveDataBank(initial_bank))
+        .insert_resource(PlayerInventory(Inventory::new()))
+        .insert_resource(PauseMenu::default())
+        .insert_resource(GameSettings(load_settings()))
+        .insert_resource(PhysicalKeys::default())
+        .insert_resource(SettingsMenu::default())
+        .insert_resource(Music::default())
         .add_startup_system(start_initial_state)
+        .add_startup_system(setup_window)
+        .add_system_to_stage(CoreStage::PreUpdate, remap_keys.after(InputSystem))
+        .add_system_to_stage(CoreStage::Last, restore_keys)
+        .add_system(run_settings_menu.before(run_pause_menu))
+        .add_system(run_pause_menu.before(manage_state_changes))
         .add_system_set(
             SystemSet::new()
-            .with_run_criteria(FixedTimestep::step(TIME_STEP as f64))
+            .with_run_criteria(FixedTimestep::step(TIME_STEP as f64).pipe(unless_paused))
             .with_system(run_current_game_state)
             .with_system(animate_sprite)
+            .with_system(follow_dive_camera.after(run_current_game_state))
+            .with_system(toggle_overlay.after(run_current_game_state))
+            .with_system(quit_from_title.after(run_current_game_state))
             .with_system(manage_state_changes.before(run_current_game_state))
         );
     }
 }
 
-impl IntroState {
+// `--dev` opens the dive map editor with [F2] in the Deep Dive Pod Room.
+// `--dive-seed <n>` swaps the map files for levels generated from the seed.
+fn dive_seed_from_args() -> Option<u64> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == "--dive-seed")?;
+
+    args.get(index + 1)?.parse().ok()
+}
+
+// `--replay <file>` boots straight into the recorded dive and plays it back.
+fn replay_from_args() -> Option<Replay> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == "--replay")?;
+    let path = args.get(index + 1)?;
+
+    match Replay::load(path) {
+        Ok(replay) => Some(replay),
+        Err(e) => {
+            warn!("Cannot load replay {}: {}", path, e);
+            None
+        }
+    }
+}
+
+impl TitleState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
+        shared: &mut SharedResources,
+    ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+
+        self.menu_index = 0;
+        self.menu_msg = String::from("");
+        self.loading = false;
+        self.opening_settings = false;
+        self.quitting = false;
+
+        commands.spawn(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.4),
+                ..default()
+            },
+            texture: asset_server.load("textures/fusion_cell.png"),
+            transform: Transform::from_scale(Vec3::splat(20.0)),
+            ..default()
+        });
+
+        self.redraw_menu(commands, asset_server, settings);
+    }
+
+    fn run(
+        &mut self,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
+        keyboard_input: Res<Input<KeyCode>>,
+        mut next_state: ResMut<NextState>,
+        inventory: &mut Inventory,
+    ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+
+        // Back from the settings screen.
+        if self.menu_entity.is_none() && !self.opening_settings {
+            self.redraw_menu(commands, asset_server, settings);
+        }
+
+        let options = if self.loading { save::save_paths().len() } else { TITLE_OPTIONS.len() };
+
+        if keyboard_input.just_pressed(KeyCode::Up) {
+            self.menu_index = (self.menu_index + options - 1) % options;
+        } else if keyboard_input.just_pressed(KeyCode::Down) {
+            self.menu_index = (self.menu_index + 1) % options;
+        } else if keyboard_input.just_pressed(KeyCode::Escape) && self.loading {
+            self.loading = false;
+            self.menu_index = TITLE_OPTIONS.iter().position(|option| *option == "Load").unwrap_or(0);
+            self.menu_msg = String::from("");
+        } else if keyboard_input.just_pressed(KeyCode::Space) && self.loading {
+            let path = save::save_paths()[self.menu_index].clone();
+            self.menu_msg = match SaveGame::load(&path) {
+                Err(_) => String::from("That slot is empty."),
+                Ok(saved) if ending_for(&saved.quest).is_some() => String::from("That story is over. Start a new game."),
+                Ok(saved) => {
+                    Self::resume(saved, &mut next_state, inventory, quest);
+                    return;
+                },
+            };
+        } else if keyboard_input.just_pressed(KeyCode::Space) {
+            match TITLE_OPTIONS[self.menu_index] {
+                "New Game" => {
+                    *quest = Quest::new();
+                    *inventory = Inventory::new();
+                    next_state.0 = State::Intro;
+                    return;
+                },
+                "Continue" => {
+                    // The newest save that has not reached an ending.
+                    let paths: Vec<String> = save::save_paths().into_iter()
+                        .filter(|path| SaveGame::load(path).is_ok_and(|saved| ending_for(&saved.quest).is_none()))
+                        .collect();
+
+                    match save::latest(&paths).and_then(|path| SaveGame::load(path).ok()) {
+                        Some(saved) => {
+                            Self::resume(saved, &mut next_state, inventory, quest);
+                            return;
+                        },
+                        None => self.menu_msg = String::from("There is no game to continue."),
+                    }
+                },
+                "Load" => {
+                    self.loading = true;
+                    self.menu_index = 0;
+                    self.menu_msg = String::from("");
+                },
+                "Settings" => {
+                    if let Some(entity) = self.menu_entity.take() {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                    self.opening_settings = true;
+                    return;
+                },
+                _ => {
+                    self.quitting = true;
+                    return;
+                },
+            }
+        } else {
+            return;
+        }
+
+        self.redraw_menu(commands, asset_server, settings);
+    }
+
+    // Loaded games pick up in the pod room, the hub between dives.
+    fn resume(saved: SaveGame, next_state: &mut ResMut<NextState>, inventory: &mut Inventory, quest: &mut Quest) {
+        *quest = saved.quest;
+        *inventory = saved.inventory;
+        next_state.0 = State::Pod;
+    }
+
+    fn redraw_menu(
+        &mut self,
         commands: &mut Commands,
         asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>
+        settings: &Settings,
+    ) {
+        if let Some(entity) = self.menu_entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        let mut menu_text = String::new();
+        if self.loading {
+            menu_text.push_str("LOAD GAME\n\n");
+            for (index, path) in save::save_paths().iter().enumerate() {
+                let cursor = if index == self.menu_index { ">" } else { " " };
+                let name = if index == 0 { String::from("Autosave") } else { format!("Slot {}", index) };
+                menu_text.push_str(&format!("{} {:<10} {}\n", cursor, name, save_summary(path)));
+            }
+            menu_text.push_str(&format!("\n{}\n\n[Up/Down] Choose  [Space] Load  [Esc] Back", self.menu_msg));
+        } else {
+            menu_text.push_str("CYBERHARVEST: DATA BREACH\n\n");
+            for (index, option) in TITLE_OPTIONS.iter().enumerate() {
+                let cursor = if index == self.menu_index { ">" } else { " " };
+                menu_text.push_str(&format!("{} {}\n", cursor, option));
+            }
+            menu_text.push_str(&format!("\n{}\n\n[Up/Down] Choose  [Space] Select", self.menu_msg));
+        }
+
+        self.menu_entity = Some(spawn_menu(commands, asset_server, settings, menu_text));
+    }
+
+    fn close(
+        &mut self,
+        commands: &mut Commands,
+        entity_query: &mut Query<Entity, Without<Camera>>,
+    ) {
+        for entity in entity_query.iter() {
+            commands.entity(entity).despawn();
+        }
+
+        self.menu_entity = None;
+    }
+}
+
+impl IntroState {
+    fn start(
+        &mut self,
+        shared: &mut SharedResources,
     ) {
-        audio.play_with_settings(
+        let SharedResources { ref mut commands, ref asset_server, ref audio, ref mut music, ref settings, .. } = *shared;
+
+        music.0 = Some(audio.play_with_settings(
             asset_server.load("sounds/music1.ogg"),
-            PlaybackSettings::ONCE.with_volume(0.5),
-        );
+            PlaybackSettings::ONCE.with_volume(settings.0.music_volume),
+        ));
 
-        self.check_progress = game_progress;
 
         self.current_story_line = 0;
 
@@ -599,12 +1389,12 @@ impl IntroState {
         mut next_state: ResMut<NextState>,
 
         mut story_query: Query<&mut Text, With<StoryText>>,
+        settings: &Settings,
     ) {
 
-        if self.current_text.len() < self.story_texts[self.current_story_line].len() {
-            let slice = self.story_texts[self.current_story_line].clone();
-
-            self.current_text = slice[..self.current_text.len() + 1].to_string();
+        let line = settings.show_keys(&self.story_texts[self.current_story_line]);
+        if self.current_text.len() < line.len() {
+            self.current_text = line[..typed_len(self.current_text.len(), line.len(), settings.text_speed)].to_string();
 
             for mut text in &mut story_query {
                 text.sections[0].value = self.current_text.clone();
@@ -625,37 +1415,40 @@ impl IntroState {
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
 
         self.story_texts.clear();
-
-        self.check_progress.clone()
     }
 }
 
 impl EndState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>,
+        quest: &Quest,
+        shared: &mut SharedResources,
     ) {
-        audio.play_with_settings(
+        let SharedResources { ref mut commands, ref asset_server, ref audio, ref mut music, ref settings, .. } = *shared;
+
+        music.0 = Some(audio.play_with_settings(
             asset_server.load("sounds/music1.ogg"),
-            PlaybackSettings::ONCE.with_volume(0.5),
-        );
+            PlaybackSettings::ONCE.with_volume(settings.0.music_volume),
+        ));
 
-        self.check_progress = game_progress;
 
         self.current_story_line = 0;
 
-        let raw_text = fs::read_to_string("assets/texts/end.txt")
-            .expect("Cannot open texts/intro.txt");
+        let ending = ending_for(quest).unwrap_or(Ending {
+            flag: String::from("ending_trap"),
+            portrait: String::from("textures/talking2.png"),
+            text_path: String::from("assets/texts/endings/trap.txt"),
+            requires: None,
+        });
+
+        let raw_text = fs::read_to_string(&ending.text_path)
+            .expect("Cannot open ending text!");
         let lines = raw_text.split('\n');
         for l in lines {
             self.story_texts.push(l.to_string());
@@ -694,7 +1487,7 @@ impl EndState {
                 color: Color::rgba(1.0, 1.0, 1.0, 1.0), //fade in later?
                 ..default()
             },
-            texture: asset_server.load("textures/talking2.png"),
+            texture: asset_server.load(&ending.portrait),
             transform: Transform::from_scale(Vec3::splat(20.0)),
             ..default()
         });
@@ -706,12 +1499,12 @@ impl EndState {
         mut next_state: ResMut<NextState>,
 
         mut story_query: Query<&mut Text, With<StoryText>>,
+        settings: &Settings,
     ) {
 
-        if self.current_text.len() < self.story_texts[self.current_story_line].len() {
-            let slice = self.story_texts[self.current_story_line].clone();
-
-            self.current_text = slice[..self.current_text.len() + 1].to_string();
+        let line = settings.show_keys(&self.story_texts[self.current_story_line]);
+        if self.current_text.len() < line.len() {
+            self.current_text = line[..typed_len(self.current_text.len(), line.len(), settings.text_speed)].to_string();
 
             for mut text in &mut story_query {
                 text.sections[0].value = self.current_text.clone();
@@ -723,7 +1516,7 @@ impl EndState {
                 self.current_story_line += 1;
                 self.current_text = String::from("");
             } else {
-             //   next_state.0 = State::Intro;
+                next_state.0 = State::Title;
             }
         }
     }
@@ -732,32 +1525,27 @@ impl EndState {
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
 
         self.story_texts.clear();
-
-        self.check_progress.clone()
     }
 }
 
 impl HelionixState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>
+        shared: &mut SharedResources,
     ) {
-        audio.play_with_settings(
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, ref audio, ref mut music, ref settings } = *shared;
+
+        music.0 = Some(audio.play_with_settings(
             asset_server.load("sounds/music1.ogg"),
-            PlaybackSettings::ONCE.with_volume(0.5),
-        );
+            PlaybackSettings::ONCE.with_volume(settings.0.music_volume),
+        ));
 
-        self.check_progress = game_progress;
 
         self.current_story_line = 0;
 
@@ -824,12 +1612,12 @@ impl HelionixState {
         mut next_state: ResMut<NextState>,
 
         mut story_query: Query<&mut Text, With<StoryText>>,
+        settings: &Settings,
     ) {
 
-        if self.current_text.len() < self.story_texts[self.current_story_line].len() {
-            let slice = self.story_texts[self.current_story_line].clone();
-
-            self.current_text = slice[..self.current_text.len() + 1].to_string();
+        let line = settings.show_keys(&self.story_texts[self.current_story_line]);
+        if self.current_text.len() < line.len() {
+            self.current_text = line[..typed_len(self.current_text.len(), line.len(), settings.text_speed)].to_string();
 
             for mut text in &mut story_query {
                 text.sections[0].value = self.current_text.clone();
@@ -850,32 +1638,27 @@ impl HelionixState {
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
 
         self.story_texts.clear();
-
-        self.check_progress.clone()
     }
 }
 
 impl FusiogenicState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>
+        shared: &mut SharedResources,
     ) {
-        audio.play_with_settings(
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, ref audio, ref mut music, ref settings } = *shared;
+
+        music.0 = Some(audio.play_with_settings(
             asset_server.load("sounds/music1.ogg"),
-            PlaybackSettings::ONCE.with_volume(0.5),
-        );
+            PlaybackSettings::ONCE.with_volume(settings.0.music_volume),
+        ));
 
-        self.check_progress = game_progress;
 
         self.current_story_line = 0;
 
@@ -942,12 +1725,12 @@ impl FusiogenicState {
         mut next_state: ResMut<NextState>,
 
         mut story_query: Query<&mut Text, With<StoryText>>,
+        settings: &Settings,
     ) {
 
-        if self.current_text.len() < self.story_texts[self.current_story_line].len() {
-            let slice = self.story_texts[self.current_story_line].clone();
-
-            self.current_text = slice[..self.current_text.len() + 1].to_string();
+        let line = settings.show_keys(&self.story_texts[self.current_story_line]);
+        if self.current_text.len() < line.len() {
+            self.current_text = line[..typed_len(self.current_text.len(), line.len(), settings.text_speed)].to_string();
 
             for mut text in &mut story_query {
                 text.sections[0].value = self.current_text.clone();
@@ -968,28 +1751,22 @@ impl FusiogenicState {
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
 
         self.story_texts.clear();
-
-        self.check_progress.clone()
     }
 }
 
 impl TechShopState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>
+        quest: &Quest,
+        shared: &mut SharedResources,
     ) {
-
-        self.check_progress = game_progress;
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
 
         self.talking_entity = Entity::from_raw(0);
         self.current_talking = 0;
@@ -1000,7 +1777,7 @@ impl TechShopState {
         self.dialog_line = 0;
 
         spawn_player(commands, &asset_server, texture_atlases, 320.0);
-        spawn_robot(commands, &asset_server, texture_atlases, -200.0);
+        spawn_npcs(commands, &asset_server, texture_atlases, State::TechShop, quest);
         spawn_background(commands, &asset_server, State::TechShop, 23.0);
         self.talking_entity = spawn_text_box(commands, &asset_server);
         spawn_story_text(commands, &asset_server);
@@ -1008,16 +1785,27 @@ impl TechShopState {
 
     fn run(
         &mut self,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
-        mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        mut story_query: Query<&mut Text, With<StoryText>>,
-        npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+        queries: RoomQueries,
+        inventory: &mut Inventory,
     ) {
-        for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
-
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+        let RoomQueries { mut player_query, mut story_query, npc_query } = queries;
+
+        if self.choice_menu.is_open() {
+            if let Some(flag) = self.choice_menu.run(commands, asset_server, settings, &keyboard_input) {
+                pick_choice(quest, &flag);
+                save_game(quest, inventory);
+            }
+            return;
+        }
+
+        for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
+
             let (mut target, mut moved) = move_player(&keyboard_input, &mut sprite, &mut timer, &mut player_transform, &mut player);
             if target.x < self.bounds.x - BORDER_MARGIN || target.x > self.bounds.y + BORDER_MARGIN {
                 target.x = 0.0;
@@ -1054,10 +1842,11 @@ impl TechShopState {
                     if keyboard_input.just_pressed(KeyCode::Space) {
                         self.talking = true;
 
+                        let folder = dialog_folder(room_name(State::TechShop), quest, npc as u32);
                         (self.dialog_state, self.dialog_line, self.talking_entity) =
                         manage_dialog(commands, &asset_server,
                                     State::TechShop,
-                                    self.check_progress.clone(),
+                                    &folder,
                                     npc as u32,
                                     self.talking_entity,
                                     self.dialog_state,
@@ -1066,6 +1855,18 @@ impl TechShopState {
                                     &mut self.current_msg,
                         );
 
+                        if self.current_msg.is_empty() {
+                            if let Some(msg) = advance_coffee_run(State::TechShop, npc as u32, quest, inventory) {
+                                self.current_msg = msg;
+                                save_game(quest, inventory);
+                            }
+
+                            let choices = open_choices("tech_shop", &folder, quest, npc as u32);
+                            if !choices.is_empty() {
+                                self.choice_menu.open(commands, asset_server, settings, choices);
+                            }
+                        }
+
                         queue_clear = true;
                     }
                 } else {
@@ -1089,7 +1890,7 @@ impl TechShopState {
                 if queue_clear && (self.talking || self.has_played_msg) {
                     clear_msg(&self.current_msg, &mut text);
                 } else {
-                    self.has_played_msg = update_msg(&self.current_msg, &mut text);
+                    self.has_played_msg = update_msg(&self.current_msg, &mut text, settings);
                 }
             }
 
@@ -1102,26 +1903,21 @@ impl TechShopState {
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
-
-        self.check_progress.clone()
     }
 }
 
 impl AlleywayState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>,
+        quest: &Quest,
+        shared: &mut SharedResources,
         spawn_x: f32,
     ) {
-        self.check_progress = game_progress;
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
 
         self.talking_entity = Entity::from_raw(0);
         self.current_talking = 0;
@@ -1132,9 +1928,7 @@ impl AlleywayState {
         self.dialog_line = 0;
 
         spawn_player(commands, &asset_server, texture_atlases, spawn_x);
-        if self.dialog_state == 0 {
-            spawn_figure(commands, &asset_server, texture_atlases, -310.0);
-        }
+        spawn_npcs(commands, &asset_server, texture_atlases, State::Alleyway, quest);
         spawn_background(commands, &asset_server, State::Alleyway, 78.0);
         self.talking_entity = spawn_text_box(commands, &asset_server);
         spawn_story_text(commands, &asset_server);
@@ -1142,14 +1936,16 @@ impl AlleywayState {
 
     fn run(
         &mut self,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
-        mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        mut story_query: Query<&mut Text, With<StoryText>>,
-        npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+        queries: RoomQueries,
     ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+        let RoomQueries { mut player_query, mut story_query, npc_query } = queries;
+
         for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
 
             let (mut target, mut moved) = move_player(&keyboard_input, &mut sprite, &mut timer, &mut player_transform, &mut player);
@@ -1180,10 +1976,11 @@ impl AlleywayState {
                     if keyboard_input.just_pressed(KeyCode::Space) {
                         self.talking = true;
 
+                        let folder = dialog_folder(room_name(State::Alleyway), quest, npc as u32);
                         (self.dialog_state, self.dialog_line, self.talking_entity) =
                         manage_dialog(commands, &asset_server,
                                     State::Alleyway,
-                                    self.check_progress.clone(),
+                                    &folder,
                                     npc as u32,
                                     self.talking_entity,
                                     self.dialog_state,
@@ -1225,7 +2022,7 @@ impl AlleywayState {
                 if queue_clear && (self.talking || self.has_played_msg) {
                     clear_msg(&self.current_msg, &mut text);
                 } else {
-                    self.has_played_msg = update_msg(&self.current_msg, &mut text);
+                    self.has_played_msg = update_msg(&self.current_msg, &mut text, settings);
                 }
             }
     
@@ -1236,35 +2033,28 @@ impl AlleywayState {
 
     fn close(
         &mut self,
+        quest: &mut Quest,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
 
         if self.dialog_state != 0 {
-            match &self.check_progress {
-                Start => self.check_progress = GameProgress::TalkToFigure,
-                _ => (),
-            }
+            quest.set("talk");
         }
-
-        self.check_progress.clone()
     }
 }
 
 impl CyberwayState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>,
+        quest: &Quest,
+        shared: &mut SharedResources,
         spawn_x: f32,
     ) {
-        self.check_progress = game_progress;
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
 
         self.talking_entity = Entity::from_raw(0);
         self.current_talking = 0;
@@ -1275,6 +2065,7 @@ impl CyberwayState {
         self.dialog_line = 0;
 
         spawn_player(commands, &asset_server, texture_atlases, spawn_x);
+        spawn_npcs(commands, &asset_server, texture_atlases, State::Cyberway, quest);
         spawn_background(commands, &asset_server, State::Cyberway, 92.0);
         self.talking_entity = spawn_text_box(commands, &asset_server);
         spawn_story_text(commands, &asset_server);
@@ -1282,14 +2073,16 @@ impl CyberwayState {
 
     fn run(
         &mut self,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
-        mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        mut story_query: Query<&mut Text, With<StoryText>>,
-        npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+        queries: RoomQueries,
     ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+        let RoomQueries { mut player_query, mut story_query, npc_query } = queries;
+
         for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
 
             let (mut target, mut moved) = move_player(&keyboard_input, &mut sprite, &mut timer, &mut player_transform, &mut player);
@@ -1313,13 +2106,21 @@ impl CyberwayState {
 
             if self.has_moved {
                 if npc != -1 && !moved {
+                    if !self.talking {
+                        let ask_npc = format!("{}: Press [Space] to talk.", npc_name(npc));
+                        if !self.current_msg.eq(&ask_npc) {
+                            self.current_msg = ask_npc;
+                        }
+                    }
+
                     if keyboard_input.just_pressed(KeyCode::Space) {
                         self.talking = true;
 
+                        let folder = dialog_folder(room_name(State::Cyberway), quest, npc as u32);
                         (self.dialog_state, self.dialog_line, self.talking_entity) =
                         manage_dialog(commands, &asset_server,
-                                    State::Alleyway,
-                                    self.check_progress.clone(),
+                                    State::Cyberway,
+                                    &folder,
                                     npc as u32,
                                     self.talking_entity,
                                     self.dialog_state,
@@ -1381,7 +2182,7 @@ impl CyberwayState {
                 if queue_clear && (self.talking || self.has_played_msg) {
                     clear_msg(&self.current_msg, &mut text);
                 } else {
-                    self.has_played_msg = update_msg(&self.current_msg, &mut text);
+                    self.has_played_msg = update_msg(&self.current_msg, &mut text, settings);
                 }
             }
 
@@ -1394,25 +2195,20 @@ impl CyberwayState {
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
-
-        self.check_progress.clone()
     }
 }
 
 impl PartsShop {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>
+        quest: &Quest,
+        shared: &mut SharedResources,
     ) {
-        self.check_progress = game_progress;
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
 
         self.talking_entity = Entity::from_raw(0);
         self.current_talking = 0;
@@ -1422,7 +2218,16 @@ impl PartsShop {
         self.talking = false;
         self.dialog_line = 0;
 
+        self.stock = inventory::load_stock(STOCK_PATH).unwrap_or_else(|e| {
+            warn!("Cannot open shop stock: {}", e);
+            Vec::new()
+        });
+        self.item_names = inventory::load_item_names(ITEM_NAMES_PATH).unwrap_or_default();
+        self.menu_entity = None;
+        self.menu_index = 0;
+
         spawn_player(commands, &asset_server, texture_atlases, -200.0);
+        spawn_npcs(commands, &asset_server, texture_atlases, State::PartsShop, quest);
         spawn_background(commands, &asset_server, State::PartsShop, 23.0);
         self.talking_entity = spawn_text_box(commands, &asset_server);
         spawn_story_text(commands, &asset_server);
@@ -1430,14 +2235,22 @@ impl PartsShop {
 
     fn run(
         &mut self,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
-        mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        mut story_query: Query<&mut Text, With<StoryText>>,
-        npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+        queries: RoomQueries,
+        inventory: &mut Inventory,
     ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+        let RoomQueries { mut player_query, mut story_query, npc_query } = queries;
+
+        if self.menu_entity.is_some() {
+            self.run_menu(commands, asset_server, settings, &keyboard_input, inventory, quest);
+            return;
+        }
+
         for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
 
             let (mut target, mut moved) = move_player(&keyboard_input, &mut sprite, &mut timer, &mut player_transform, &mut player);
@@ -1461,23 +2274,16 @@ impl PartsShop {
 
             if self.has_moved {
                 if npc != -1 && !moved {
+                    if !self.current_msg.eq(&self.ask_shopkeeper) {
+                        self.current_msg = self.ask_shopkeeper.clone();
+                    }
 
                     if keyboard_input.just_pressed(KeyCode::Space) {
-                        self.talking = true;
-
-                        (self.dialog_state, self.dialog_line, self.talking_entity) =
-                        manage_dialog(commands, &asset_server,
-                                    State::PartsShop,
-                                    self.check_progress.clone(),
-                                    npc as u32,
-                                    self.talking_entity,
-                                    self.dialog_state,
-                                    self.dialog_line,
-                                    &mut self.dialog_texts,
-                                    &mut self.current_msg,
-                        );
+                        commands.entity(self.talking_entity).despawn();
+                        self.talking_entity = spawn_talking_entity(commands, &asset_server, npc as u32);
 
-                        queue_clear = true;
+                        self.menu_msg = String::from("Looking for an upgrade?");
+                        self.redraw_menu(commands, asset_server, settings, inventory);
                     }
                 } else {
                     if target.x < -180.0 && !moved {
@@ -1500,7 +2306,7 @@ impl PartsShop {
                 if queue_clear && (self.talking || self.has_played_msg) {
                     clear_msg(&self.current_msg, &mut text);
                 } else {
-                    self.has_played_msg = update_msg(&self.current_msg, &mut text);
+                    self.has_played_msg = update_msg(&self.current_msg, &mut text, settings);
                 }
             }
 
@@ -1509,30 +2315,97 @@ impl PartsShop {
         }
     }
 
+    // Buy menu: [Up]/[Down] to choose, [Space] to buy, [Esc] to leave.
+    fn run_menu(
+        &mut self,
+        commands: &mut Commands,
+        asset_server: &Res<AssetServer>,
+        settings: &Settings,
+        keyboard_input: &Res<Input<KeyCode>>,
+        inventory: &mut Inventory,
+        quest: &Quest,
+    ) {
+        if keyboard_input.just_pressed(KeyCode::Escape) {
+            if let Some(entity) = self.menu_entity.take() {
+                commands.entity(entity).despawn_recursive();
+            }
+            return;
+        }
+
+        if self.stock.is_empty() {
+            return;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Up) {
+            self.menu_index = (self.menu_index + self.stock.len() - 1) % self.stock.len();
+        } else if keyboard_input.just_pressed(KeyCode::Down) {
+            self.menu_index = (self.menu_index + 1) % self.stock.len();
+        } else if keyboard_input.just_pressed(KeyCode::Space) {
+            let (item, price) = self.stock[self.menu_index].clone();
+            let name = self.item_name(&item);
+
+            // Parts are one of a kind, there is no use for a second.
+            self.menu_msg = if inventory.has(&item) {
+                format!("You already have the {}.", name)
+            } else if inventory.take(CREDITS, price) {
+                inventory.give(&item, 1);
+                save_game(quest, inventory);
+                format!("Bought the {}. Pleasure doing business.", name)
+            } else {
+                format!("The {} costs {} credits. Come back with more.", name, price)
+            };
+        } else {
+            return;
+        }
+
+        self.redraw_menu(commands, asset_server, settings, inventory);
+    }
+
+    fn redraw_menu(
+        &mut self,
+        commands: &mut Commands,
+        asset_server: &Res<AssetServer>,
+        settings: &Settings,
+        inventory: &Inventory,
+    ) {
+        if let Some(entity) = self.menu_entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        let mut menu_text = format!("CYBER PARTS   Credits: {}\n\n", inventory.count(CREDITS));
+        for (index, (item, price)) in self.stock.iter().enumerate() {
+            let cursor = if index == self.menu_index { ">" } else { " " };
+            let owned = if inventory.has(item) { "  (owned)" } else { "" };
+            menu_text.push_str(&format!("{} {:<24} {:>4}{}\n", cursor, self.item_name(item), price, owned));
+        }
+        menu_text.push_str(&format!("\n{}\n\n[Up/Down] Choose  [Space] Buy  [Esc] Leave", self.menu_msg));
+
+        self.menu_entity = Some(spawn_menu(commands, asset_server, settings, menu_text));
+    }
+
+    fn item_name(&self, item: &str) -> String {
+        self.item_names.get(item).cloned().unwrap_or_else(|| item.to_string())
+    }
+
     fn close(
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
-
-        self.check_progress.clone()
     }
 }
 
 impl CafeState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>,
+        quest: &Quest,
+        shared: &mut SharedResources,
         spawn_x: f32,
     ) {
-        self.check_progress = game_progress;
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
 
         self.talking_entity = Entity::from_raw(0);
         self.current_talking = 0;
@@ -1543,6 +2416,7 @@ impl CafeState {
         self.dialog_line = 0;
 
         spawn_player(commands, &asset_server, texture_atlases, spawn_x);
+        spawn_npcs(commands, &asset_server, texture_atlases, State::Cafe, quest);
         spawn_background(commands, &asset_server, State::Cafe, 23.0);
         self.talking_entity = spawn_text_box(commands, &asset_server);
         spawn_story_text(commands, &asset_server);
@@ -1550,14 +2424,25 @@ impl CafeState {
 
     fn run(
         &mut self,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
-        mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        mut story_query: Query<&mut Text, With<StoryText>>,
-        npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+        queries: RoomQueries,
+        inventory: &mut Inventory,
     ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+        let RoomQueries { mut player_query, mut story_query, npc_query } = queries;
+
+        if self.choice_menu.is_open() {
+            if let Some(flag) = self.choice_menu.run(commands, asset_server, settings, &keyboard_input) {
+                pick_choice(quest, &flag);
+                save_game(quest, inventory);
+            }
+            return;
+        }
+
         for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
 
             let (mut target, mut moved) = move_player(&keyboard_input, &mut sprite, &mut timer, &mut player_transform, &mut player);
@@ -1581,21 +2466,43 @@ impl CafeState {
 
             if self.has_moved {
                 if npc != -1 && !moved {
+                    if !self.talking {
+                        let ask_npc = format!("{}: Press [Space] to talk.", npc_name(npc));
+                        if !self.current_msg.eq(&ask_npc) {
+                            self.current_msg = ask_npc;
+                        }
+                    }
 
                     if keyboard_input.just_pressed(KeyCode::Space) {
                         self.talking = true;
 
-                        (self.dialog_state, self.dialog_line, self.talking_entity) =
+                        let folder = dialog_folder(room_name(State::Cafe), quest, npc as u32);
+                        let key = (npc as u32, folder.clone());
+                        let mut dialog_state = self.dialog_states.get(&key).copied().unwrap_or(0);
+                        (dialog_state, self.dialog_line, self.talking_entity) =
                         manage_dialog(commands, &asset_server,
-                                    State::TechShop,
-                                    self.check_progress.clone(),
+                                    State::Cafe,
+                                    &folder,
                                     npc as u32,
                                     self.talking_entity,
-                                    self.dialog_state,
+                                    dialog_state,
                                     self.dialog_line,
                                     &mut self.dialog_texts,
                                     &mut self.current_msg,
                         );
+                        self.dialog_states.insert(key, dialog_state);
+
+                        if self.current_msg.is_empty() {
+                            if let Some(msg) = advance_coffee_run(State::Cafe, npc as u32, quest, inventory) {
+                                self.current_msg = msg;
+                                save_game(quest, inventory);
+                            }
+
+                            let choices = open_choices("cafe", &folder, quest, npc as u32);
+                            if !choices.is_empty() {
+                                self.choice_menu.open(commands, asset_server, settings, choices);
+                            }
+                        }
 
                         queue_clear = true;
                     }
@@ -1630,7 +2537,7 @@ impl CafeState {
                 if queue_clear && (self.talking || self.has_played_msg) {
                     clear_msg(&self.current_msg, &mut text);
                 } else {
-                    self.has_played_msg = update_msg(&self.current_msg, &mut text);
+                    self.has_played_msg = update_msg(&self.current_msg, &mut text, settings);
                 }
             }
 
@@ -1643,35 +2550,34 @@ impl CafeState {
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
-
-        self.check_progress.clone()
     }
 }
 
 impl PodState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>
+        quest: &Quest,
+        shared: &mut SharedResources,
     ) {
-        self.check_progress = game_progress;
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
         
         self.talking_entity = Entity::from_raw(0);
         self.current_talking = 0;
         self.has_played_msg = false;
         self.has_moved = false;
-        self.current_msg = self.location_string.clone();
+        self.current_msg = self.dive_result.take().unwrap_or(self.location_string.clone());
         self.talking = false;
         self.dialog_line = 0;
+        self.item_names = inventory::load_item_names(ITEM_NAMES_PATH).unwrap_or_default();
+        self.menu_entity = None;
+        self.menu_index = 0;
 
         spawn_player(commands, &asset_server, texture_atlases, -200.0);
+        spawn_npcs(commands, &asset_server, texture_atlases, State::Pod, quest);
         spawn_background(commands, &asset_server, State::Pod, 23.0);
         self.talking_entity = spawn_text_box(commands, &asset_server);
         spawn_story_text(commands, &asset_server);
@@ -1679,14 +2585,31 @@ impl PodState {
 
     fn run(
         &mut self,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
-        mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        mut story_query: Query<&mut Text, With<StoryText>>,
-        npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+        queries: RoomQueries,
+        inventory: &Inventory,
     ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+        let RoomQueries { mut player_query, mut story_query, npc_query } = queries;
+
+        if self.dev_mode && keyboard_input.just_pressed(KeyCode::F2) {
+            next_state.0 = State::Editor;
+        }
+
+        if self.menu_entity.is_some() {
+            self.run_menu(commands, asset_server, settings, &keyboard_input, inventory, quest);
+            return;
+        }
+        if keyboard_input.just_pressed(KeyCode::E) {
+            self.menu_msg = format!("{} slots. Fitted parts work on every dive.", CYBERWARE_SLOTS);
+            self.redraw_menu(commands, asset_server, settings, inventory, quest);
+            return;
+        }
+
        for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
 
             let (mut target, mut moved) = move_player(&keyboard_input, &mut sprite, &mut timer, &mut player_transform, &mut player);
@@ -1714,10 +2637,11 @@ impl PodState {
                     if keyboard_input.just_pressed(KeyCode::Space) {
                         self.talking = true;
 
+                        let folder = dialog_folder(room_name(State::Pod), quest, npc as u32);
                         (self.dialog_state, self.dialog_line, self.talking_entity) =
                         manage_dialog(commands, &asset_server,
-                                    State::TechShop,
-                                    self.check_progress.clone(),
+                                    State::Pod,
+                                    &folder,
                                     npc as u32,
                                     self.talking_entity,
                                     self.dialog_state,
@@ -1741,12 +2665,7 @@ impl PodState {
                         } 
                     } else if target.x > -60.0 && target.x < 190.0 && !moved {
 
-                        let has_key = match &self.check_progress {
-                            GameProgress::GetFirstKey => true,
-                            GameProgress::GetSecondKey => true,
-                            GameProgress::GetFinalKey => true,
-                            _ => false,
-                        };
+                        let has_key = inventory.data_key().is_some();
 
                         if has_key {
                             commands.entity(self.talking_entity).despawn();
@@ -1756,17 +2675,11 @@ impl PodState {
                                 self.current_msg = self.enter_pod.clone();
                             }
                             if keyboard_input.just_pressed(KeyCode::W) {
-                                
-                                let is_final = match &self.check_progress {
-                                    GameProgress::GetFinalKey => true,
-                                    _ => false,
+                                // The cutscene is the one of the corporation the key breaks into.
+                                next_state.0 = match dive_bank(quest).and_then(|(_, set)| Faction::from_name(&set.owner)) {
+                                    Some(Faction::Fusiogenic) => State::Fusiogenic,
+                                    _ => State::Helionix,
                                 };
-
-                                if is_final {
-                                    next_state.0 = State::Fusiogenic;
-                                } else {
-                                    next_state.0 = State::Helionix;
-                                }
                             } 
                         } else {
                             queue_clear = true;
@@ -1784,7 +2697,7 @@ impl PodState {
                 if queue_clear && (self.talking || self.has_played_msg) {
                     clear_msg(&self.current_msg, &mut text);
                 } else {
-                    self.has_played_msg = update_msg(&self.current_msg, &mut text);
+                    self.has_played_msg = update_msg(&self.current_msg, &mut text, settings);
                 }
             }
 
@@ -1793,29 +2706,99 @@ impl PodState {
         }
     }
 
+    fn run_menu(
+        &mut self,
+        commands: &mut Commands,
+        asset_server: &Res<AssetServer>,
+        settings: &Settings,
+        keyboard_input: &Res<Input<KeyCode>>,
+        inventory: &Inventory,
+        quest: &mut Quest,
+    ) {
+        if keyboard_input.just_pressed(KeyCode::Escape) || keyboard_input.just_pressed(KeyCode::E) {
+            if let Some(entity) = self.menu_entity.take() {
+                commands.entity(entity).despawn_recursive();
+            }
+            return;
+        }
+
+        let owned = cyberware::owned(inventory);
+        if owned.is_empty() {
+            return;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Up) {
+            self.menu_index = (self.menu_index + owned.len() - 1) % owned.len();
+        } else if keyboard_input.just_pressed(KeyCode::Down) {
+            self.menu_index = (self.menu_index + 1) % owned.len();
+        } else if keyboard_input.just_pressed(KeyCode::Space) {
+            let part = owned[self.menu_index.min(owned.len() - 1)];
+            let name = self.item_name(part.item());
+
+            self.menu_msg = if !cyberware::toggle(quest, inventory, part) {
+                format!("No free slot for the {}.", name)
+            } else if quest.has(&part.flag()) {
+                format!("Fitted the {}.", name)
+            } else {
+                format!("Took out the {}.", name)
+            };
+            save_game(quest, inventory);
+        } else {
+            return;
+        }
+
+        self.redraw_menu(commands, asset_server, settings, inventory, quest);
+    }
+
+    fn redraw_menu(
+        &mut self,
+        commands: &mut Commands,
+        asset_server: &Res<AssetServer>,
+        settings: &Settings,
+        inventory: &Inventory,
+        quest: &Quest,
+    ) {
+        if let Some(entity) = self.menu_entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        let owned = cyberware::owned(inventory);
+        let mut menu_text = format!("CYBERWARE   Slots: {}/{}\n\n", cyberware::equipped(quest, inventory).len(), CYBERWARE_SLOTS);
+        if owned.is_empty() {
+            menu_text.push_str("  No parts. The parts shop sells them.\n");
+        }
+        for (index, part) in owned.iter().enumerate() {
+            let cursor = if index == self.menu_index { ">" } else { " " };
+            let fitted = if quest.has(&part.flag()) { "[x]" } else { "[ ]" };
+            menu_text.push_str(&format!("{} {} {}\n", cursor, fitted, self.item_name(part.item())));
+        }
+        menu_text.push_str(&format!("\n{}\n\n[Up/Down] Choose  [Space] Fit/Remove  [Esc] Close", self.menu_msg));
+
+        self.menu_entity = Some(spawn_menu(commands, asset_server, settings, menu_text));
+    }
+
+    fn item_name(&self, item: &str) -> String {
+        self.item_names.get(item).cloned().unwrap_or_else(|| item.to_string())
+    }
+
     fn close(
         &mut self,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
-
-        self.check_progress.clone()
     }
 }
 
 impl Alleyway2State {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>
+        quest: &Quest,
+        shared: &mut SharedResources,
     ) {
-        self.check_progress = game_progress.clone();
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
 
         self.talking_entity = Entity::from_raw(0);
         self.current_talking = 0;
@@ -1827,15 +2810,9 @@ impl Alleyway2State {
 
         spawn_player(commands, &asset_server, texture_atlases, -450.0);
 
-        match &game_progress {
-            GameProgress::Start => (),
-            GameProgress::GetFirstKey => (),
-            GameProgress::GetSecondKey => (),
-            GameProgress::GetFinalKey => (),
-            _ => {
-                self.dialog_state = 0;
-                spawn_figure(commands, &asset_server, texture_atlases, 310.0)
-            },
+        // A fresh talk with the Figure each time, for the next handover.
+        if spawn_npcs(commands, &asset_server, texture_atlases, State::Alleyway2, quest) > 0 {
+            self.dialog_state = 0;
         }
 
         spawn_background(commands, &asset_server, State::Alleyway2, 78.0);
@@ -1845,14 +2822,28 @@ impl Alleyway2State {
 
     fn run(
         &mut self,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
+        quest: &mut Quest,
+        shared: &mut SharedResources,
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
-        mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        mut story_query: Query<&mut Text, With<StoryText>>,
-        npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+        queries: RoomQueries,
+        inventory: &Inventory,
     ) {
+        let SharedResources { ref mut commands, ref asset_server, ref settings, .. } = *shared;
+        let settings = &settings.0;
+        let RoomQueries { mut player_query, mut story_query, npc_query } = queries;
+
+        if self.choice_menu.is_open() {
+            if let Some(flag) = self.choice_menu.run(commands, asset_server, settings, &keyboard_input) {
+                pick_choice(quest, &flag);
+                save_game(quest, inventory);
+                if ending_for(quest).is_some() {
+                    next_state.0 = State::End;
+                }
+            }
+            return;
+        }
+
         for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
 
             let (mut target, mut moved) = move_player(&keyboard_input, &mut sprite, &mut timer, &mut player_transform, &mut player);
@@ -1883,10 +2874,21 @@ impl Alleyway2State {
                     if keyboard_input.just_pressed(KeyCode::Space) {
                         self.talking = true;
 
+                        // Once the job is handed out, fragments brought back open up the Figure's past.
+                        if self.dialog_state != 0 && self.dialog_texts.is_empty() {
+                            let fragments = quest.count(FRAGMENTS_FOUND) as u32;
+                            if let Some((needed, lore)) = next_lore(quest.count(LORE_TOLD) as u32, fragments) {
+                                quest.set_count(LORE_TOLD, needed as i64);
+                                save_game(quest, inventory);
+                                self.dialog_texts = lore;
+                            }
+                        }
+
+                        let folder = dialog_folder(room_name(State::Alleyway2), quest, npc as u32);
                         (self.dialog_state, self.dialog_line, self.talking_entity) =
                         manage_dialog(commands, &asset_server,
                                     State::Alleyway2,
-                                    self.check_progress.clone(),
+                                    &folder,
                                     npc as u32,
                                     self.talking_entity,
                                     self.dialog_state,
@@ -1895,6 +2897,13 @@ impl Alleyway2State {
                                     &mut self.current_msg,
                         );
 
+                        if self.current_msg.is_empty() {
+                            let choices = open_choices("alleyway2", &folder, quest, npc as u32);
+                            if !choices.is_empty() {
+                                self.choice_menu.open(commands, asset_server, settings, choices);
+                            }
+                        }
+
                         queue_clear = true;
                     }
                 } else {
@@ -1918,7 +2927,7 @@ impl Alleyway2State {
                 if queue_clear && (self.talking || self.has_played_msg) {
                     clear_msg(&self.current_msg, &mut text);
                 } else {
-                    self.has_played_msg = update_msg(&self.current_msg, &mut text);
+                    self.has_played_msg = update_msg(&self.current_msg, &mut text, settings);
                 }
             }
     
@@ -1929,116 +2938,111 @@ impl Alleyway2State {
 
     fn close(
         &mut self,
+        quest: &mut Quest,
         commands: &mut Commands,
         entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
+        inventory: &mut Inventory,
+    ) {
         for entity in entity_query.iter() {
             commands.entity(entity).despawn();
         }
 
         if self.dialog_state != 0 {
-            match &self.check_progress {
-                GameProgress::TalkToFigure => self.check_progress = GameProgress::GetFirstKey,
-                GameProgress::GetFirstData => self.check_progress = GameProgress::GetSecondKey,
-                GameProgress::GetSecondData => self.check_progress = GameProgress::GetFinalKey,
-                _ => (),
+            // The next key is only handed over for the data from the last one.
+            let handover = match GameProgress::of(quest) {
+                GameProgress::TalkToFigure => Some((None, "first_key")),
+                GameProgress::GetFirstData => Some((Some("first_data"), "second_key")),
+                GameProgress::GetSecondData if quest.has("take_final_key") => Some((Some("second_data"), "final_key")),
+                _ => None,
+            };
+
+            if let Some((data, key)) = handover {
+                let paid = match data {
+                    Some(data) => inventory.take(data, 1),
+                    None => true,
+                };
+
+                if paid {
+                    quest.set(key);
+                    inventory.give(key, 1);
+                }
             }
         }
-
-        self.check_progress.clone()
     }
 }
 
 impl DeepDiveState {
     fn start(
         &mut self,
-        game_progress: GameProgress,
-        commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
-        audio: &Res<Audio>,
+        shared: &mut SharedResources,
         mut deep_dive_data_bank: ResMut<DeepDiveDataBank>,
     ) {
-        self.check_progress = game_progress;
-
-        let mut tiles: Vec<Entity> = Vec::new();
-        let mut map_lines: Vec<String> = Vec::new();
-
-        let mut path = String::from("assets/dives/map");
-        path.push_str(&deep_dive_data_bank.0.to_string()[..]);
-        path.push_str("-");
-        path.push_str(&self.level.to_string()[..]);
-        path.push_str(".txt");
-
-        let raw_text = fs::read_to_string(path)
-            .expect("Cannot open map!");
-        let lines = raw_text.split('\n');
-        for l in lines {
-            map_lines.push(l.to_string());
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, ref settings, .. } = *shared;
+        let settings = &settings.0;
+
+        if let Some(map) = &self.test_map {
+            self.map = map.clone();
+        } else if let Some(seed) = self.seed {
+            let difficulty = Difficulty::for_level(deep_dive_data_bank.0, self.level, self.is_last_level());
+            let level_seed = generator::level_seed(seed, deep_dive_data_bank.0, self.level);
+
+            // Seeds next to the level's own get a go before the hand made map
+            // stands in, so a replay rebuilds the same levels.
+            match (0..GENERATOR_RETRIES).find_map(|retry| generator::generate(level_seed.wrapping_add(retry), &difficulty)) {
+                Some((map, _)) => self.map = map,
+                None => {
+                    warn!("Cannot generate level {} from seed {}, using the dive set's map.", self.level + 1, seed);
+                    self.map = self.dive_set_map();
+                },
+            }
+        } else {
+            self.map = self.dive_set_map();
+        }
+        // Fragments already picked up stay gone when the level is rebuilt after
+        // a death, but keep hidden tiles for an undo to bring back.
+        self.fragments_on_level = self.map.fragments().len() as u32;
+        let level_map = self.map.clone();
+        let gone: Vec<Position> = self.collected.iter().chain(&self.broken).copied().collect();
+        for position in &gone {
+            self.map.set_tile(*position, Tile::Floor);
         }
 
-        let half_map: i32 = map_lines.len() as i32 / 2;
-
-        for y in 0..map_lines.len() {
-            let line = map_lines[y as usize].clone();
-            for x in 0..line.len() {
-                let tile_char = line.chars().nth(x as usize).unwrap();
-
-                let mut transform = Transform::from_scale(Vec3::splat(DEEP_DIVE_TILE_SCALE));
-                transform.translation.x = (x as f32 - half_map as f32) * DEEP_DIVE_TILE_SCALE;
-                transform.translation.y = (y as f32 - half_map as f32) * DEEP_DIVE_TILE_SCALE;
-
-                let mut color = Color::rgba(1.0, 1.0, 1.0, 1.0);
-
-                if tile_char == '#' {
-                    color = Color::rgba(1.0, 1.0, 1.0, 1.0);
-                }
-                if tile_char == '~' {
-                    color = Color::rgba(1.0, 0.0, 0.0, 1.0);
-                }
-                if tile_char == '@' {
-                    color = Color::rgba(0.0, 1.0, 1.0, 1.0);
-                }
-                if tile_char == '$' {
-                    color = Color::rgba(0.0, 1.0, 0.0, 1.0);
-                }
+        self.position = self.map.spawn;
+        self.sliding = None;
+        self.level_par = dive::solve(&self.map).map_or(0, |solution| solution.moves.len() as u32);
 
-                if tile_char == '#' || tile_char == '~' || tile_char == '@' || tile_char == '$' {
-                    let tile = commands.spawn(SpriteBundle {
-                        sprite: Sprite {
-                            color,
-                            ..default()
-                        },
-                        transform,
-                        ..default()
-                    }).id();
+        let font = asset_server.load("fonts/PressStart2P-Regular.ttf");
+        let text_style = TextStyle {
+            font,
+            font_size: 16.0,
+            color: Color::WHITE,
+        };
 
-                    if tile_char == '#' {
-                        commands.entity(tile).insert(Collider);
-                    }
-                    if tile_char == '~' {
-                        commands.entity(tile).insert(Lava);
-                    }
-                    if tile_char == '@' {
-                        commands.entity(tile).insert(DataPort);
-                    }
-                    if tile_char == '$' {
-                        commands.entity(tile).insert(Portal);
-                    }
-                }
-            }
-        }
+        commands.spawn((
+            TextBundle::from_section(
+                self.hud_text(settings),
+                text_style,
+            ).with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Percent(2.0),
+                    top: Val::Percent(3.0),
+                    ..default()
+                },
+                ..default()
+            }),
+            StoryText
+        ));
 
-        let mut transform = Transform::from_scale(Vec3::splat(DEEP_DIVE_TILE_SCALE));
-        transform.translation.x = 0.0 as f32 * DEEP_DIVE_TILE_SCALE;
-        transform.translation.y = 0.0 as f32 * DEEP_DIVE_TILE_SCALE;
+        spawn_dive_tiles(commands, asset_server, texture_atlases, &level_map, &gone);
 
         let texture_handle = asset_server.load("textures/player_walk.png");
         let texture_atlas =
             TextureAtlas::from_grid(texture_handle, Vec2::new(32.0, 32.0), 1, 8, None, None);
         let texture_atlas_handle = texture_atlases.add(texture_atlas);
 
-        let mut player_transform = Transform::from_scale(Vec3::splat(2.0));
+        let mut player_transform = Transform::from_scale(Vec3::splat(self.respawn_scale()));
+        player_transform.translation = dive_tile_translation(&self.map, self.position);
         player_transform.translation.z = 100.0;
         let mut player_anim_timer = Timer::from_seconds(0.1, TimerMode::Repeating);
 
@@ -2062,166 +3066,1059 @@ impl DeepDiveState {
         keyboard_input: Res<Input<KeyCode>>,
         mut next_state: ResMut<NextState>,
         mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-        wall_query: Query<&Transform, (Without<Player>, With<Collider>)>,
-        lava_query: Query<&Transform, (Without<Player>, With<Lava>)>,
-        data_query: Query<&Transform, (Without<Player>, With<DataPort>)>,
-        portal_query: Query<&Transform, (Without<Player>, With<Portal>)>,
-
-        mut deep_dive_data_bank: ResMut<DeepDiveDataBank>,
+        mut story_query: Query<&mut Text, With<StoryText>>,
+        mut tile_query: Query<(&DiveTileAt, &mut Visibility)>,
+        settings: &Settings,
     ) {
-        for (mut sprite, mut timer, mut player_transform, mut player) in player_query.iter_mut() {
-            
-            if player.velocity.x == 0.0 && player.velocity.y == 0.0 {
-                if keyboard_input.just_pressed(KeyCode::A) {
-                    player.velocity.x = -DEEP_DIVE_TILE_SCALE;
-                } else if keyboard_input.just_pressed(KeyCode::W) {
-                    player.velocity.y = DEEP_DIVE_TILE_SCALE;
-                } else if keyboard_input.just_pressed(KeyCode::S) {
-                    player.velocity.y = -DEEP_DIVE_TILE_SCALE;
-                } else if keyboard_input.just_pressed(KeyCode::D) {
-                    player.velocity.x = DEEP_DIVE_TILE_SCALE;
+        for (mut sprite, _, mut player_transform, _) in player_query.iter_mut() {
+
+            if self.dying > 0 {
+                self.dying -= 1;
+                sprite.color = if (self.dying / 4).is_multiple_of(2) { Color::RED } else { Color::rgba(1.0, 1.0, 1.0, 0.2) };
+                if self.dying == 0 {
+                    self.respawning = DIVE_RESPAWN_FRAMES;
+                    next_state.0 = State::DeepDive;
                 }
+                continue;
             }
-    
-            let target = Vec3::new(player_transform.translation.x + player.velocity.x,
-                player_transform.translation.y + player.velocity.y, 0.0);
-    
-            if dive_collision_check(target, &wall_query, &lava_query, &data_query, &portal_query, &mut self.level, &mut deep_dive_data_bank, &mut next_state) {
-                player_transform.translation = target;
-            } else {
-                player.velocity.x = 0.0;
-                player.velocity.y = 0.0;
-            }
-    
-            if keyboard_input.just_pressed(KeyCode::P) {
-                next_state.0 = State::TechShop;
+
+            if self.respawning > 0 {
+                self.respawning -= 1;
+                player_transform.scale = Vec3::splat(self.respawn_scale());
+                continue;
             }
-        }
-    }
 
-    fn close(
-        &mut self,
-        commands: &mut Commands,
-        entity_query: &mut Query<Entity, Without<Camera>>,
-    ) -> GameProgress {
-        for entity in entity_query.iter() {
-            commands.entity(entity).despawn();
-        }
+            if self.sliding.is_none() {
+                if self.replaying && self.playback.is_empty() && self.playback_delay == 0 {
+                    self.end_playback();
+                    next_state.0 = State::Title;
+                    continue;
+                }
 
-        self.check_progress.clone()
-    }
-}
+                let input = if self.replaying {
+                    self.next_playback_input()
+                } else {
+                    read_dive_input(&keyboard_input)
+                };
+
+                match input {
+                    Some(DiveInput::Slide(direction)) if !self.rig.blocks(&self.map, self.position, direction) => {
+                        self.level_moves += 1;
+                        self.dive_moves += 1;
+                        self.raise_trace(DIVE_TRACE_PER_MOVE);
+                        self.push_history();
+                        self.recording.push(DiveInput::Slide(direction));
+                        self.sliding = Some(direction);
+                    },
+                    Some(DiveInput::Slide(_)) => (),
+                    Some(DiveInput::Undo) => {
+                        // Also rewinds a lava death, since the history survives the level reload.
+                        if let Some(restored) = self.undo() {
+                            self.recording.push(DiveInput::Undo);
+                            self.raise_trace(DIVE_TRACE_PER_MOVE);
+
+                            for (tile, mut visibility) in tile_query.iter_mut() {
+                                if restored.contains(&tile.0) {
+                                    visibility.is_visible = true;
+                                }
+                            }
 
-fn run_current_game_state(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut next_state: ResMut<NextState>,
-  
-    mut player_query: Query<(&mut TextureAtlasSprite, &mut AnimationTimer, &mut Transform, &mut Player), With<Player>>,
-    story_query: Query<&mut Text, With<StoryText>>,
-    npc_query: Query<(&NPC, &Transform), (Without<Player>, With<NPC>)>,
+                            let target = dive_tile_translation(&self.map, self.position);
+                            player_transform.translation.x = target.x;
+                            player_transform.translation.y = target.y;
+                        }
+                    },
+                    Some(DiveInput::Brake) if self.rig.arm_brake() => {
+                        self.recording.push(DiveInput::Brake);
+                    },
+                    Some(DiveInput::Brake) => (),
+                    Some(DiveInput::Breach) if self.rig.arm_breaker() => {
+                        self.recording.push(DiveInput::Breach);
+                    },
+                    Some(DiveInput::Breach) => (),
+                    Some(DiveInput::Restart) => {
+                        self.restart_level();
+                        next_state.0 = State::DeepDive;
+                    },
+                    None => (),
+                }
+            }
 
-    wall_query: Query<&Transform, (Without<Player>, With<Collider>)>,
-    lava_query: Query<&Transform, (Without<Player>, With<Lava>)>,
-    data_query: Query<&Transform, (Without<Player>, With<DataPort>)>,
-    portal_query: Query<&Transform, (Without<Player>, With<Portal>)>,
+            if let Some(direction) = self.sliding {
+                let breaching = self.rig.breaching;
+                match self.rig.step(&mut self.map, self.position, direction) {
+                    Step::Moved(position, sliding) => {
+                        self.position = position;
+                        self.sliding = sliding;
 
-    mut deep_dive_data_bank: ResMut<DeepDiveDataBank>,
+                        let broke_wall = breaching && !self.rig.breaching;
+                        if broke_wall {
+                            self.broken.push(position);
+                        }
+                        let picked_up = self.map.tile(position) == Tile::Fragment;
+                        if picked_up {
+                            self.collect_fragment(position);
+                        }
+                        if broke_wall || picked_up {
+                            for (tile, mut visibility) in tile_query.iter_mut() {
+                                if tile.0 == position {
+                                    visibility.is_visible = false;
+                                }
+                            }
+                        }
+                    },
+                    Step::Blocked => self.sliding = None,
+                    Step::Lava(position) => {
+                        self.position = position;
+                        self.sliding = None;
+                        self.dying = DIVE_DEATH_FRAMES;
+                        self.add_death();
+                    },
+                    Step::DataPort(position) if self.test_map.is_some() => {
+                        self.position = position;
+                        next_state.0 = State::Editor;
+                    },
+                    Step::Portal(position) if self.test_map.is_some() => {
+                        self.position = position;
+                        next_state.0 = State::Editor;
+                    },
+                    Step::DataPort(position) if !self.is_last_level() => {
+                        self.position = position;
+                        self.finish_level();
+                        self.level += 1;
+                        next_state.0 = State::DeepDive;
+                    },
+                    Step::DataPort(position) | Step::Portal(position) => {
+                        self.position = position;
+                        self.finish_level();
+                        next_state.0 = State::Pod;
+                    },
+                }
+
+                let target = dive_tile_translation(&self.map, self.position);
+                player_transform.translation.x = target.x;
+                player_transform.translation.y = target.y;
+            }
+
+            self.raise_trace(self.trace_rate);
+            if self.trace >= 1.0 && !self.traced_out {
+                self.traced_out = true;
+                next_state.0 = State::Pod;
+            }
+
+            if self.test_map.is_some() && keyboard_input.just_pressed(KeyCode::Escape) {
+                next_state.0 = State::Editor;
+            }
+        }
+
+        let hud_text = self.hud_text(settings);
+        let hud_color = if self.trace >= 0.75 { Color::RED } else { Color::WHITE };
+        for mut text in &mut story_query {
+            if text.sections[0].value != hud_text {
+                text.sections[0].value = hud_text.clone();
+            }
+            text.sections[0].style.color = hud_color;
+        }
+    }
+
+    fn dive_set_map(&self) -> DiveMap {
+        let path = self.dive_set.map_path(self.level as usize)
+            .expect("No map for this dive level!");
+
+        DiveMap::load(path)
+            .expect("Cannot open map!")
+    }
+
+    fn begin_dive(&mut self, bank: u32, dive_set: DiveSet, fitted: &[Cyberware], quest: &Quest) {
+        self.bank = bank;
+        self.dive_set = dive_set;
+        self.recording.clear();
+        self.replaying = false;
+        self.level = 0;
+        self.level_moves = 0;
+        self.completed_par = 0;
+        self.dive_moves = 0;
+        self.history.clear();
+        self.undo_limit = DIVE_UNDO_LIMITS[(bank as usize).min(DIVE_UNDO_LIMITS.len() - 1)];
+        self.level_deaths.clear();
+        self.level_fragments.clear();
+        self.collected.clear();
+        self.dying = 0;
+        self.respawning = 0;
+        self.trace = 0.0;
+        self.trace_rate = TIME_STEP / DIVE_TRACE_SECONDS[(bank as usize).min(DIVE_TRACE_SECONDS.len() - 1)];
+        self.traced_out = false;
+        self.abandoned = false;
+        self.rig = Rig::fit(fitted);
+        self.level_rig = self.rig.clone();
+        self.trace_rate *= self.rig.trace_factor();
+        if let Some(owner) = Faction::from_name(&self.dive_set.owner) {
+            self.trace_rate *= reputation::trace_factor(quest, owner);
+        }
+        self.broken.clear();
+
+        self.dive_id = match self.seed {
+            Some(seed) => format!("seed{}-{}", seed, bank),
+            None => format!("map{}", bank),
+        };
+    }
+
+    fn finish_level(&mut self) {
+        self.collected.clear();
+        self.broken.clear();
+        self.completed_par += self.level_par;
+        self.level_moves = 0;
+        self.history.clear();
+        self.level_rig = self.rig.clone();
+    }
+
+    // Back to the level as it was entered: the fragments return, broken walls
+    // close and the parts get their charges back. The trace and moves stay.
+    fn restart_level(&mut self) {
+        if let Some(found) = self.level_fragments.get_mut(self.level as usize) {
+            *found -= self.collected.len() as u32;
+        }
+        self.collected.clear();
+        self.broken.clear();
+        self.history.clear();
+        self.rig = self.level_rig.clone();
+        self.dying = 0;
+        self.respawning = 0;
+        self.recording.push(DiveInput::Restart);
+    }
+
+    // Test play from the editor: normal rules, but reaching the goal or
+    // pressing [Esc] goes back to editing instead of advancing the dive.
+    fn begin_test(&mut self, map: DiveMap) {
+        let dive_set = DiveSet { key: String::from(""), owner: String::from(""), levels: Vec::new() };
+        self.begin_dive(0, dive_set, &[], &Quest::new());
+        self.trace_rate = 0.0;
+        self.test_map = Some(map);
+    }
+
+    // Plays a recorded dive through the same rules as live input.
+    // False when the replay's data bank is not in the dive sets.
+    fn begin_playback(&mut self, replay: Replay) -> bool {
+        let dive_set = match DiveSet::load_all(DIVE_SETS_PATH) {
+            Ok(sets) => sets.get(replay.bank as usize).cloned(),
+            Err(e) => {
+                warn!("Cannot open dive sets: {}", e);
+                return false;
+            },
+        };
+        let dive_set = match dive_set {
+            Some(dive_set) => dive_set,
+            None => {
+                warn!("No dive for data bank {}.", replay.bank);
+                return false;
+            },
+        };
+
+        self.seed = replay.seed;
+        self.begin_dive(replay.bank, dive_set, &replay.cyberware, &Quest::new());
+        self.playback = replay.inputs.into_iter().collect();
+        self.playback_delay = REPLAY_MOVE_DELAY;
+        self.replaying = true;
+        self.replay_end = replay.end;
+        true
+    }
+
+    // The recorded run stopped short of the data: it ends the same way here,
+    // without touching the game it was recorded in.
+    fn end_playback(&mut self) {
+        match self.replay_end {
+            Some(DiveEnd::TracedOut) => {
+                self.trace = 1.0;
+                self.traced_out = true;
+            },
+            Some(DiveEnd::Abandoned) => self.abandoned = true,
+            None => (),
+        }
+    }
+
+    fn next_playback_input(&mut self) -> Option<DiveInput> {
+        if self.playback_delay > 0 {
+            self.playback_delay -= 1;
+            return None;
+        }
+
+        self.playback_delay = REPLAY_MOVE_DELAY;
+        self.playback.pop_front()
+    }
+
+    fn save_replay(&self, new_best: bool) {
+        let replay = Replay {
+            bank: self.bank,
+            seed: self.seed,
+            cyberware: self.rig.fitted.clone(),
+            inputs: self.recording.clone(),
+            end: self.dive_end(),
+        };
+
+        let mut paths = vec![format!("{}/{}-last.txt", REPLAY_DIR, self.dive_id)];
+        if new_best {
+            paths.push(format!("{}/{}-best.txt", REPLAY_DIR, self.dive_id));
+        }
+
+        for path in paths {
+            if let Err(e) = replay.save(&path) {
+                warn!("Cannot save replay {}: {}", path, e);
+            }
+        }
+    }
+
+    fn dive_end(&self) -> Option<DiveEnd> {
+        if self.traced_out {
+            Some(DiveEnd::TracedOut)
+        } else if self.abandoned {
+            Some(DiveEnd::Abandoned)
+        } else {
+            None
+        }
+    }
+
+    fn reward(&self) -> u32 {
+        let grade = Grade::rate(self.dive_moves, self.completed_par);
+
+        DIVE_REWARDS[grade as usize] + FRAGMENT_REWARD * self.stats().fragments()
+    }
+
+    fn is_last_level(&self) -> bool {
+        self.level as usize + 1 >= self.dive_set.levels.len()
+    }
+
+    fn add_death(&mut self) {
+        let level = self.level as usize;
+        if self.level_deaths.len() <= level {
+            self.level_deaths.resize(level + 1, 0);
+        }
+        self.level_deaths[level] += 1;
+    }
+
+    fn collect_fragment(&mut self, position: Position) {
+        self.map.set_tile(position, Tile::Floor);
+        self.collected.push(position);
+
+        let level = self.level as usize;
+        if self.level_fragments.len() <= level {
+            self.level_fragments.resize(level + 1, 0);
+        }
+        self.level_fragments[level] += 1;
+    }
+
+    fn respawn_scale(&self) -> f32 {
+        2.0 * (1.0 - self.respawning as f32 / DIVE_RESPAWN_FRAMES as f32)
+    }
+
+    fn stats(&self) -> DiveStats {
+        DiveStats {
+            bank: self.bank,
+            moves: self.dive_moves,
+            level_deaths: self.level_deaths.clone(),
+            level_fragments: self.level_fragments.clone(),
+            traced_out: self.traced_out,
+        }
+    }
+
+    // Security is not watching test plays or replays.
+    fn raise_trace(&mut self, amount: f32) {
+        if self.test_map.is_none() && !self.replaying {
+            self.trace = (self.trace + amount).min(1.0);
+        }
+    }
+
+    fn push_history(&mut self) {
+        if self.undo_limit == 0 {
+            return;
+        }
+
+        if self.history.len() == self.undo_limit {
+            self.history.remove(0);
+        }
+        self.history.push(DiveUndo {
+            position: self.position,
+            rig: self.rig.clone(),
+            broken: self.broken.clone(),
+            collected: self.collected.clone(),
+        });
+    }
+
+    // Back to the last history entry: walls broken and fragments picked up
+    // since then return. Gives the positions of the tiles that came back.
+    fn undo(&mut self) -> Option<Vec<Position>> {
+        let undo = self.history.pop()?;
+
+        let walls: Vec<Position> = self.broken.iter().copied().filter(|position| !undo.broken.contains(position)).collect();
+        let fragments: Vec<Position> = self.collected.iter().copied().filter(|position| !undo.collected.contains(position)).collect();
+        for position in &walls {
+            self.map.set_tile(*position, Tile::Wall);
+        }
+        for position in &fragments {
+            self.map.set_tile(*position, Tile::Fragment);
+        }
+        if let Some(found) = self.level_fragments.get_mut(self.level as usize) {
+            *found -= fragments.len() as u32;
+        }
+
+        self.position = undo.position;
+        self.rig = undo.rig;
+        self.broken = undo.broken;
+        self.collected = undo.collected;
+
+        Some(walls.into_iter().chain(fragments).collect())
+    }
+
+    // Grades the dive against the summed level pars and keeps the best run.
+    fn finish_dive(&self) -> String {
+        let grade = Grade::rate(self.dive_moves, self.completed_par);
+
+        let mut scores = DiveScores::load(SCORES_PATH);
+        let previous_best = scores.best(&self.dive_id);
+
+        let mut result = format!("Dive rating: {} ({} moves, par {}).", grade.letter(), self.dive_moves, self.completed_par);
+
+        // A replay only shows a run that was already scored and saved.
+        if self.replaying {
+            return result;
+        }
+
+        let new_best = scores.record(&self.dive_id, self.dive_moves);
+        self.save_replay(new_best);
+
+        if new_best {
+            if let Err(e) = scores.save(SCORES_PATH) {
+                warn!("Cannot save dive scores: {}", e);
+            }
+            if previous_best.is_some() {
+                result.push_str(" New best!");
+            }
+        } else if let Some(best) = previous_best {
+            result.push_str(&format!(" Best: {}.", Grade::rate(best, self.completed_par).letter()));
+        }
+
+        result
+    }
+
+    fn hud_text(&self, settings: &Settings) -> String {
+        let mut hud_text = format!("Level {}  Moves: {} (par {})  Dive: {}", self.level + 1, self.level_moves, self.level_par, self.dive_moves);
+        let level_deaths = self.level_deaths.get(self.level as usize).copied().unwrap_or(0);
+        hud_text.push_str(&format!("  Deaths: {} ({} total)", level_deaths, self.stats().deaths()));
+        if self.fragments_on_level > 0 {
+            hud_text.push_str(&format!("  Fragments: {}/{}", self.collected.len(), self.fragments_on_level));
+        }
+        if self.undo_limit > 0 {
+            hud_text.push_str(&format!("  [Z] Undo: {}", self.history.len()));
+        }
+        let rig_text = self.rig.hud_text();
+        if !rig_text.is_empty() {
+            hud_text.push_str(&format!("\n{}", rig_text));
+        }
+        if self.replaying {
+            hud_text.push_str("  REPLAY");
+        } else if self.test_map.is_none() {
+            let filled = (self.trace * 20.0) as usize;
+            hud_text.push_str(&format!("\nTrace [{}{}] {}%", "#".repeat(filled), "-".repeat(20 - filled), (self.trace * 100.0) as u32));
+        }
+
+        settings.show_keys(&hud_text)
+    }
+
+    fn close(
+        &mut self,
+        commands: &mut Commands,
+        entity_query: &mut Query<Entity, Without<Camera>>,
+    ) {
+        for entity in entity_query.iter() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn run_current_game_state(
+    mut shared: SharedResources,
+    keyboard_input: Res<Input<KeyCode>>,
+    next_state: ResMut<NextState>,
+    queries: RoomQueries,
+    editor_input: EditorInput,
+    dive_tile_at_query: Query<(&DiveTileAt, &mut Visibility)>,
+    mut inventory: ResMut<PlayerInventory>,
 
     current_state: Res<CurrentState>,
     mut states: ResMut<StateData>,
 ) {
+    let states = &mut states.0;
+
     match current_state.0 {
+        State::Title => {
+            states.title_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, &mut inventory.0);
+        },
         State::Intro => {
-            states.0.intro_state.run(keyboard_input, next_state, story_query);
+            states.intro_state.run(keyboard_input, next_state, queries.story_query, &shared.settings.0);
         },
         State::End => {
-            states.0.end_state.run(keyboard_input, next_state, story_query);
+            states.end_state.run(keyboard_input, next_state, queries.story_query, &shared.settings.0);
         },
         State::Helionix => {
-            states.0.helionix_state.run(keyboard_input, next_state, story_query);
+            states.helionix_state.run(keyboard_input, next_state, queries.story_query, &shared.settings.0);
         },
         State::Fusiogenic => {
-            states.0.fusiogenic_state.run(keyboard_input, next_state, story_query);
+            states.fusiogenic_state.run(keyboard_input, next_state, queries.story_query, &shared.settings.0);
         },
         State::TechShop => {
-            states.0.tech_shop_state.run(&mut commands, &asset_server, keyboard_input, next_state, player_query, story_query, npc_query);
+            states.tech_shop_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, queries, &mut inventory.0);
         },
         State::Alleyway => {
-            states.0.alleyway_state.run(&mut commands, &asset_server, keyboard_input, next_state, player_query, story_query, npc_query);
+            states.alleyway_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, queries);
         },
         State::Cyberway => {
-            states.0.cyberway_state.run(&mut commands, &asset_server, keyboard_input, next_state, player_query, story_query, npc_query);
+            states.cyberway_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, queries);
         },
         State::PartsShop => {
-            states.0.parts_shop_state.run(&mut commands, &asset_server, keyboard_input, next_state, player_query, story_query, npc_query);
+            states.parts_shop_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, queries, &mut inventory.0);
         },
         State::Cafe => {
-            states.0.cafe_state.run(&mut commands, &asset_server, keyboard_input, next_state, player_query, story_query, npc_query);
+            states.cafe_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, queries, &mut inventory.0);
         },
         State::Pod => {
-            states.0.pod_state.run(&mut commands, &asset_server, keyboard_input, next_state, player_query, story_query, npc_query);
+            states.pod_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, queries, &inventory.0);
         },
         State::Alleyway2 => {
-            states.0.alleyway2_state.run(&mut commands, &asset_server, keyboard_input, next_state, player_query, story_query, npc_query);
+            states.alleyway2_state.run(&mut states.quest, &mut shared, keyboard_input, next_state, queries, &inventory.0);
         },
         State::DeepDive => {
-            states.0.deep_dive_state.run(keyboard_input, next_state, player_query, wall_query, lava_query, data_query, portal_query, deep_dive_data_bank);
+            states.deep_dive_state.run(keyboard_input, next_state, queries.player_query, queries.story_query, dive_tile_at_query, &shared.settings.0);
+        },
+        State::Editor => {
+            states.editor_state.run(&mut shared, keyboard_input, next_state, queries.story_query, editor_input);
         }
     }
 }
 
+impl EditorState {
+    fn start(
+        &mut self,
+        commands: &mut Commands,
+        asset_server: &Res<AssetServer>,
+    ) {
+
+        self.files = dive::map_paths(MAP_DIR)
+            .map(|paths| paths.iter().map(|path| path.display().to_string()).collect())
+            .unwrap_or_default();
+
+        // Keep the map between test plays, only load one on the first visit.
+        if self.map.width == 0 {
+            self.file_index = 0;
+            match self.files.first() {
+                Some(path) => self.load(path.clone()),
+                None => self.new_map(),
+            }
+        }
+
+        self.redraw = true;
+        self.last_mouse = None;
+
+        let font = asset_server.load("fonts/PressStart2P-Regular.ttf");
+        let text_style = TextStyle {
+            font,
+            font_size: 12.0,
+            color: Color::WHITE,
+        };
+
+        commands.spawn((
+            TextBundle::from_section(
+                "",
+                text_style,
+            ).with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Percent(2.0),
+                    top: Val::Percent(2.0),
+                    ..default()
+                },
+                ..default()
+            }),
+            StoryText
+        ));
+
+        let mut cursor_transform = Transform::from_scale(Vec3::splat(DEEP_DIVE_TILE_SCALE));
+        cursor_transform.translation.z = 50.0;
+
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgba(1.0, 1.0, 0.0, 0.35),
+                    ..default()
+                },
+                transform: cursor_transform,
+                ..default()
+            },
+            EditorCursor,
+        ));
+    }
+
+    fn run(
+        &mut self,
+        shared: &mut SharedResources,
+        keyboard_input: Res<Input<KeyCode>>,
+        mut next_state: ResMut<NextState>,
+        mut story_query: Query<&mut Text, With<StoryText>>,
+        editor_input: EditorInput,
+    ) {
+        let SharedResources { ref mut commands, ref asset_server, ref mut texture_atlases, .. } = *shared;
+        let EditorInput { mouse_input, windows, mut cursor_query, tile_query } = editor_input;
+
+        let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+        let (width, height) = (self.map.width, self.map.height);
+
+        if keyboard_input.just_pressed(KeyCode::Left) {
+            if shift {
+                self.resize(width.saturating_sub(1), height);
+            } else {
+                self.cursor.0 -= 1;
+            }
+        } else if keyboard_input.just_pressed(KeyCode::Right) {
+            if shift {
+                self.resize(width + 1, height);
+            } else {
+                self.cursor.0 += 1;
+            }
+        } else if keyboard_input.just_pressed(KeyCode::Up) {
+            if shift {
+                self.resize(width, height.saturating_sub(1));
+            } else {
+                self.cursor.1 -= 1;
+            }
+        } else if keyboard_input.just_pressed(KeyCode::Down) {
+            if shift {
+                self.resize(width, height + 1);
+            } else {
+                self.cursor.1 += 1;
+            }
+        }
+
+        let mut view = Vec2::ZERO;
+        if let Some(window) = windows.get_primary() {
+            view = Vec2::new(window.width(), window.height());
+
+            // The mouse only takes over the cursor when it actually moves.
+            if let Some(mouse) = window.cursor_position() {
+                if self.last_mouse != Some(mouse) {
+                    self.last_mouse = Some(mouse);
+                    self.cursor = dive_tile_at(&self.map, mouse - view / 2.0 + self.camera);
+                }
+            }
+        }
+
+        self.cursor.0 = self.cursor.0.clamp(0, self.map.width as i32 - 1);
+        self.cursor.1 = self.cursor.1.clamp(0, self.map.height as i32 - 1);
+
+        let brush_keys = [
+            (KeyCode::Key1, EditorBrush::Tile(Tile::Wall)),
+            (KeyCode::Key2, EditorBrush::Tile(Tile::Lava)),
+            (KeyCode::Key3, EditorBrush::Tile(Tile::DataPort)),
+            (KeyCode::Key4, EditorBrush::Tile(Tile::Portal)),
+            (KeyCode::Key5, EditorBrush::Spawn),
+            (KeyCode::Key6, EditorBrush::Tile(Tile::Conveyor(Direction::Right))),
+            (KeyCode::Key7, EditorBrush::Tile(Tile::Stopper)),
+            (KeyCode::Key8, EditorBrush::Tile(Tile::Fragment)),
+        ];
+        for (key, brush) in brush_keys {
+            if keyboard_input.just_pressed(key) {
+                self.brush = brush;
+            }
+        }
+
+        if keyboard_input.just_pressed(KeyCode::R) {
+            if let EditorBrush::Tile(Tile::Conveyor(direction)) = self.brush {
+                let next = match direction {
+                    Direction::Right => Direction::Down,
+                    Direction::Down => Direction::Left,
+                    Direction::Left => Direction::Up,
+                    Direction::Up => Direction::Right,
+                };
+                self.brush = EditorBrush::Tile(Tile::Conveyor(next));
+            }
+        }
+
+        if keyboard_input.pressed(KeyCode::Space) || mouse_input.pressed(MouseButton::Left) {
+            self.paint(self.brush);
+        } else if keyboard_input.pressed(KeyCode::Delete) || keyboard_input.pressed(KeyCode::Back) || mouse_input.pressed(MouseButton::Right) {
+            self.paint(EditorBrush::Tile(Tile::Floor));
+        }
+
+        if keyboard_input.just_pressed(KeyCode::PageDown) && !self.files.is_empty() {
+            self.file_index = (self.file_index + 1) % self.files.len();
+            self.load(self.files[self.file_index].clone());
+        } else if keyboard_input.just_pressed(KeyCode::PageUp) && !self.files.is_empty() {
+            self.file_index = (self.file_index + self.files.len() - 1) % self.files.len();
+            self.load(self.files[self.file_index].clone());
+        } else if keyboard_input.just_pressed(KeyCode::N) {
+            self.new_map();
+        }
+
+        if keyboard_input.just_pressed(KeyCode::F5) {
+            self.save();
+        }
+
+        if keyboard_input.just_pressed(KeyCode::T) {
+            next_state.0 = State::DeepDive;
+        } else if keyboard_input.just_pressed(KeyCode::Escape) {
+            next_state.0 = State::Pod;
+        }
+
+        if self.redraw {
+            self.redraw = false;
+
+            for entity in tile_query.iter() {
+                commands.entity(entity).despawn();
+            }
+            spawn_dive_tiles(commands, asset_server, texture_atlases, &self.map, &[]);
+
+            let mut spawn_transform = Transform::from_scale(Vec3::splat(DEEP_DIVE_TILE_SCALE * 0.5));
+            spawn_transform.translation = dive_tile_translation(&self.map, self.map.spawn);
+            spawn_transform.translation.z = 10.0;
+
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgba(0.2, 1.0, 0.2, 0.8),
+                        ..default()
+                    },
+                    transform: spawn_transform,
+                    ..default()
+                },
+                DiveTile,
+            ));
+
+            self.solver_status = match dive::solve(&self.map) {
+                Some(solution) => {
+                    let exit = match solution.exit {
+                        Exit::DataPort => "data port",
+                        Exit::Portal => "portal",
+                    };
+                    format!("Solvable: {} move(s) to the {} ({})", solution.moves.len(), exit, solution.keys())
+                },
+                None => String::from("Unsolvable!"),
+            };
+        }
+
+        let cursor_translation = dive_tile_translation(&self.map, self.cursor);
+        self.camera = dive_camera_target(&self.map, cursor_translation, view);
+
+        for mut cursor_transform in cursor_query.iter_mut() {
+            cursor_transform.translation.x = cursor_translation.x;
+            cursor_transform.translation.y = cursor_translation.y;
+        }
+
+        let brush_name = match self.brush {
+            EditorBrush::Tile(Tile::Conveyor(direction)) => format!("Conveyor {:?}", direction),
+            EditorBrush::Tile(tile) => format!("{:?}", tile),
+            EditorBrush::Spawn => String::from("Spawn"),
+        };
+
+        let hud_text = format!(
+            "{} ({}x{})  Cursor {},{}\n\
+            Brush: {}  [1-8] Brush  [R] Rotate  [Space/LMB] Paint  [Del/RMB] Erase\n\
+            [Arrows] Move  [Shift+Arrows] Resize  [PgUp/PgDn] Files  [N] New\n\
+            [T] Test  [F5] Save  [Esc] Exit\n\
+            {}\n{}",
+            self.current_path(), self.map.width, self.map.height, self.cursor.0, self.cursor.1,
+            brush_name, self.solver_status, self.message,
+        );
+
+        for mut text in &mut story_query {
+            if text.sections[0].value != hud_text {
+                text.sections[0].value = hud_text.clone();
+            }
+        }
+    }
+
+    fn paint(&mut self, brush: EditorBrush) {
+        let before = (self.map.tile(self.cursor), self.map.spawn);
+
+        match brush {
+            EditorBrush::Tile(tile) => self.map.set_tile(self.cursor, tile),
+            EditorBrush::Spawn => {
+                self.map.set_tile(self.cursor, Tile::Floor);
+                self.map.spawn = self.cursor;
+            },
+        }
+
+        if (self.map.tile(self.cursor), self.map.spawn) != before {
+            self.redraw = true;
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.map.resize(width, height);
+        self.redraw = true;
+    }
+
+    fn current_path(&self) -> String {
+        self.files.get(self.file_index).cloned().unwrap_or_default()
+    }
+
+    fn load(&mut self, path: String) {
+        match DiveMap::load(&path) {
+            Ok(map) => {
+                // An empty file parses to a 0x0 map, which has no tile for the cursor.
+                self.map = map;
+                self.map.resize(self.map.width, self.map.height);
+                self.message = format!("Loaded {}", path);
+            },
+            Err(e) => {
+                self.map = DiveMap::new(9, 9);
+                self.message = format!("Cannot open {}: {}", path, e);
+            },
+        }
+
+        self.cursor = self.map.spawn;
+        self.redraw = true;
+    }
+
+    // A walled 9x9 room saved under the first free custom map name.
+    fn new_map(&mut self) {
+        let mut map = DiveMap::new(9, 9);
+        for y in 0..9 {
+            for x in 0..9 {
+                if x == 0 || y == 0 || x == 8 || y == 8 {
+                    map.set_tile((x, y), Tile::Wall);
+                }
+            }
+        }
+
+        let mut number = 0;
+        let path = loop {
+            let path = format!("{}/custom{}.txt", MAP_DIR, number);
+            if !self.files.contains(&path) && fs::metadata(&path).is_err() {
+                break path;
+            }
+            number += 1;
+        };
+
+        self.files.push(path.clone());
+        self.file_index = self.files.len() - 1;
+        self.map = map;
+        self.cursor = self.map.spawn;
+        self.message = format!("New map {}", path);
+        self.redraw = true;
+    }
+
+    fn save(&mut self) {
+        let path = self.current_path();
+        self.message = match self.map.save(&path) {
+            Ok(()) => format!("Saved {}", path),
+            Err(e) => format!("Cannot save {}: {}", path, e),
+        };
+    }
+
+    fn close(
+        &mut self,
+        commands: &mut Commands,
+        entity_query: &mut Query<Entity, Without<Camera>>,
+    ) {
+        for entity in entity_query.iter() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn follow_dive_camera(
+    windows: Res<Windows>,
+    current_state: Res<CurrentState>,
+    states: Res<StateData>,
+    player_query: Query<&Transform, (With<Player>, Without<Camera>)>,
+    mut camera_query: Query<&mut Transform, (With<Camera>, Without<Player>)>,
+) {
+    let target = match current_state.0 {
+        State::DeepDive => {
+            // The player is briefly missing or doubled while a level reloads.
+            let (window, player_transform) = match (windows.get_primary(), player_query.get_single()) {
+                (Some(window), Ok(player_transform)) => (window, player_transform),
+                _ => return,
+            };
+
+            let view = Vec2::new(window.width(), window.height());
+            dive_camera_target(&states.0.deep_dive_state.map, player_transform.translation, view)
+        },
+        State::Editor => states.0.editor_state.camera,
+        _ => Vec2::ZERO,
+    };
+
+    for mut camera_transform in camera_query.iter_mut() {
+        camera_transform.translation.x = target.x;
+        camera_transform.translation.y = target.y;
+    }
+}
+
+// [J] opens the quest journal and [I] the inventory over any room the player
+// can walk around in. The same key closes it again.
+fn toggle_overlay(
+    shared: SharedResources,
+    keyboard_input: Res<Input<KeyCode>>,
+    current_state: Res<CurrentState>,
+    states: Res<StateData>,
+    inventory: Res<PlayerInventory>,
+    overlay_query: Query<(Entity, &Overlay)>,
+) {
+    let SharedResources { mut commands, asset_server, settings, .. } = shared;
+
+    let key = match [KeyCode::J, KeyCode::I].into_iter().find(|key| keyboard_input.just_pressed(*key)) {
+        Some(key) => key,
+        None => return,
+    };
+
+    let mut was_open = false;
+    for (entity, overlay) in overlay_query.iter() {
+        was_open |= overlay.0 == key;
+        commands.entity(entity).despawn_recursive();
+    }
+
+    match current_state.0 {
+        State::TechShop | State::Alleyway | State::Cyberway | State::PartsShop | State::Cafe | State::Pod | State::Alleyway2 => (),
+        _ => return,
+    }
+
+    if was_open {
+        return;
+    }
+
+    let font = asset_server.load("fonts/PressStart2P-Regular.ttf");
+    let heading_style = TextStyle {
+        font: font.clone(),
+        font_size: 20.0,
+        color: Color::rgb(0.0, 0.9, 1.0),
+    };
+    let line_style = TextStyle {
+        font: font.clone(),
+        font_size: 14.0,
+        color: Color::WHITE,
+    };
+    let faded_style = TextStyle {
+        font,
+        font_size: 14.0,
+        color: Color::GRAY,
+    };
+
+    let mut sections = Vec::new();
+    if key == KeyCode::J {
+        let journal = Journal::load(JOURNAL_PATH).unwrap_or_else(|e| {
+            warn!("Cannot open journal: {}", e);
+            Journal::default()
+        });
+
+        sections.push(TextSection::new("JOURNAL\n\nObjectives:\n", heading_style.clone()));
+        for objective in journal.active(&states.0.quest) {
+            sections.push(TextSection::new(format!("> {}\n", objective.text), line_style.clone()));
+        }
+        sections.push(TextSection::new("\nCompleted:\n", heading_style));
+        for objective in journal.completed(&states.0.quest) {
+            sections.push(TextSection::new(format!("- {}\n", objective.text), faded_style.clone()));
+        }
+    } else {
+        let names = inventory::load_item_names(ITEM_NAMES_PATH).unwrap_or_default();
+
+        sections.push(TextSection::new("INVENTORY\n\n", heading_style.clone()));
+        for (item, count) in inventory.0.items() {
+            let name = names.get(item).map_or(item, |name| name.as_str());
+            let line = if count > 1 { format!("> {} x{}\n", name, count) } else { format!("> {}\n", name) };
+            sections.push(TextSection::new(line, line_style.clone()));
+        }
+        if inventory.0.items().next().is_none() {
+            sections.push(TextSection::new("Nothing.\n", faded_style));
+        }
+
+        sections.push(TextSection::new("\nStanding:\n", heading_style));
+        for faction in Faction::ALL {
+            let name = if faction == Faction::Helionix { "Helionix Industries" } else { "Fusiogenic Solutions" };
+            let line = format!("> {}: {:+}\n", name, reputation::standing(&states.0.quest, faction));
+            sections.push(TextSection::new(line, line_style.clone()));
+        }
+    }
+    sections.push(TextSection::new(settings.0.show_keys(&format!("\n[{:?}] Close", key)), line_style));
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Percent(10.0),
+                    top: Val::Percent(8.0),
+                    ..default()
+                },
+                size: Size::new(Val::Percent(80.0), Val::Percent(70.0)),
+                padding: UiRect::all(Val::Px(24.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.05, 0.9).into(),
+            z_index: ZIndex::Global(10),
+            ..default()
+        },
+        Overlay(key),
+    )).with_children(|parent| {
+        parent.spawn(TextBundle::from_sections(sections).with_style(Style {
+            flex_wrap: FlexWrap::Wrap,
+            max_size: Size {
+                width: Val::Percent(100.0),
+                height: Val::Undefined,
+            },
+            ..default()
+        }));
+    });
+}
+
 fn start_initial_state(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    mut shared: SharedResources,
     current_state: Res<CurrentState>,
     mut states: ResMut<StateData>,
 
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    audio: Res<Audio>,
-
     mut deep_dive_data_bank: ResMut<DeepDiveDataBank>,
 ) {
 
-    let mut game_progress = states.0.game_progress.clone();
+    let states = &mut states.0;
 
     match current_state.0 {
+        State::Title => {
+            states.title_state.start(&mut shared);
+        },
         State::Intro => {
-            states.0.intro_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.intro_state.start(&mut shared);
         },
         State::End => {
-            states.0.end_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.end_state.start(&states.quest, &mut shared);
         },
         State::Helionix => {
-            states.0.helionix_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.helionix_state.start(&mut shared);
         },
         State::Fusiogenic => {
-            states.0.fusiogenic_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.fusiogenic_state.start(&mut shared);
         },
         State::TechShop => {
-            states.0.tech_shop_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.tech_shop_state.start(&states.quest, &mut shared);
         },
         State::Alleyway => {
-            states.0.alleyway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 0.0);
+            states.alleyway_state.start(&states.quest, &mut shared, 0.0);
         },
         State::Cyberway => {
-            states.0.cyberway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 0.0);
+            states.cyberway_state.start(&states.quest, &mut shared, 0.0);
         },
         State::PartsShop => {
-            states.0.parts_shop_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.parts_shop_state.start(&states.quest, &mut shared);
         },
         State::Cafe => {
-            states.0.cafe_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 0.0);
+            states.cafe_state.start(&states.quest, &mut shared, 0.0);
         },
         State::Pod => {
-            states.0.pod_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.pod_state.start(&states.quest, &mut shared);
         },
         State::Alleyway2 => {
-            states.0.alleyway2_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+            states.alleyway2_state.start(&states.quest, &mut shared);
         },
         State::DeepDive => {
-            states.0.deep_dive_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, deep_dive_data_bank);
+            states.deep_dive_state.start(&mut shared, deep_dive_data_bank);
+        },
+        State::Editor => {
+            states.editor_state.start(&mut shared.commands, &shared.asset_server);
         }
     }
 }
@@ -2232,231 +4129,388 @@ fn manage_state_changes(
     mut states: ResMut<StateData>,
 
     mut deep_dive_data_bank: ResMut<DeepDiveDataBank>,
+    mut inventory: ResMut<PlayerInventory>,
 
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    mut shared: SharedResources,
     mut entity_query: Query<Entity, Without<Camera>>,
-    audio: Res<Audio>,
 ) {
     if next_state.is_changed() && !next_state.is_added() {
+        let states = &mut states.0;
 
         match (&current_state.0, &next_state.0) {
+            (State::Title, State::Intro) => {
+                states.title_state.close(&mut shared.commands, &mut entity_query);
+                states.restart();
+                current_state.0 = State::Intro;
+                states.intro_state.start(&mut shared);
+            },
+            (State::Title, State::Pod) => {
+                states.title_state.close(&mut shared.commands, &mut entity_query);
+                states.restart();
+                current_state.0 = State::Pod;
+                states.pod_state.start(&states.quest, &mut shared);
+            },
+            (State::End, State::Title) => {
+                states.end_state.close(&mut shared.commands, &mut entity_query);
+                current_state.0 = State::Title;
+                states.title_state.start(&mut shared);
+            },
+            (_, State::Title) => {
+                // Quit from the pause menu: anything unsaved goes, the rooms
+                // start over when a game is picked.
+                for entity in entity_query.iter() {
+                    shared.commands.entity(entity).despawn();
+                }
+                current_state.0 = State::Title;
+                states.title_state.start(&mut shared);
+            },
+
             (State::Intro, State::TechShop) => {
-                states.0.game_progress = states.0.intro_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.intro_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::TechShop;
-                states.0.tech_shop_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                states.tech_shop_state.start(&states.quest, &mut shared);
             },
 
             (State::TechShop, State::Alleyway) => {
-                states.0.game_progress = states.0.tech_shop_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.tech_shop_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Alleyway;
-                states.0.alleyway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 210.0);
+                states.alleyway_state.start(&states.quest, &mut shared, 210.0);
             },
 
             (State::Alleyway, State::TechShop) => {
-                states.0.game_progress = states.0.alleyway_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.alleyway_state.close(&mut states.quest, &mut shared.commands, &mut entity_query);
                 current_state.0 = State::TechShop;
-                states.0.tech_shop_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                states.tech_shop_state.start(&states.quest, &mut shared);
             },
             (State::Alleyway, State::Cyberway) => {
-                states.0.game_progress = states.0.alleyway_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.alleyway_state.close(&mut states.quest, &mut shared.commands, &mut entity_query);
                 current_state.0 = State::Cyberway;
-                states.0.cyberway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, -580.0);
+                states.cyberway_state.start(&states.quest, &mut shared, -580.0);
             },
 
             (State::Cyberway, State::Alleyway) => {
-                states.0.game_progress = states.0.cyberway_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.cyberway_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Alleyway;
-                states.0.alleyway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 510.0);
+                states.alleyway_state.start(&states.quest, &mut shared, 510.0);
             },
             (State::Cyberway, State::Cafe) => {
-                states.0.game_progress = states.0.cyberway_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.cyberway_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Cafe;
-                states.0.cafe_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 350.0);
+                states.cafe_state.start(&states.quest, &mut shared, 350.0);
             },
             (State::Cyberway, State::Alleyway2) => {
-                states.0.game_progress = states.0.cyberway_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.cyberway_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Alleyway2;
-                states.0.alleyway2_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                states.alleyway2_state.start(&states.quest, &mut shared);
             },
             (State::Cyberway, State::PartsShop) => {
-                states.0.game_progress = states.0.cyberway_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.cyberway_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::PartsShop;
-                states.0.parts_shop_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                states.parts_shop_state.start(&states.quest, &mut shared);
             },
 
             (State::PartsShop, State::Cyberway) => {
-                states.0.game_progress = states.0.parts_shop_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.parts_shop_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Cyberway;
-                states.0.cyberway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, -350.0);
+                states.cyberway_state.start(&states.quest, &mut shared, -350.0);
             },
 
+            (State::Alleyway2, State::End) => {
+                states.alleyway2_state.close(&mut states.quest, &mut shared.commands, &mut entity_query, &mut inventory.0);
+                current_state.0 = State::End;
+                states.end_state.start(&states.quest, &mut shared);
+            },
             (State::Alleyway2, State::Cyberway) => {
-                states.0.game_progress = states.0.alleyway2_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.alleyway2_state.close(&mut states.quest, &mut shared.commands, &mut entity_query, &mut inventory.0);
                 current_state.0 = State::Cyberway;
-                states.0.cyberway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 520.0);
+                states.cyberway_state.start(&states.quest, &mut shared, 520.0);
             },
 
             (State::Cafe, State::Cyberway) => {
-                states.0.game_progress = states.0.cafe_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.cafe_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Cyberway;
-                states.0.cyberway_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, 430.0);
+                states.cyberway_state.start(&states.quest, &mut shared, 430.0);
             },
             (State::Cafe, State::Pod) => {
-                states.0.game_progress = states.0.cafe_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.cafe_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Pod;
-                states.0.pod_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                states.pod_state.start(&states.quest, &mut shared);
             },
 
             (State::Pod, State::Helionix) => {
-                states.0.game_progress = states.0.pod_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.pod_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Helionix;
-                states.0.helionix_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                states.helionix_state.start(&mut shared);
             },
             (State::Pod, State::Fusiogenic) => {
-                states.0.game_progress = states.0.pod_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.pod_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Fusiogenic;
-                states.0.fusiogenic_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                states.fusiogenic_state.start(&mut shared);
             },
             
             (State::Helionix, State::DeepDive) => {
-                states.0.game_progress = states.0.helionix_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
-                current_state.0 = State::DeepDive;
-                states.0.deep_dive_state.level = 0;
-                states.0.deep_dive_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, deep_dive_data_bank);
+                states.helionix_state.close(&mut shared.commands, &mut entity_query);
+                if let Some((bank, dive_set)) = dive_bank(&states.quest) {
+                    current_state.0 = State::DeepDive;
+                    deep_dive_data_bank.0 = bank;
+                    let fitted = cyberware::equipped(&states.quest, &inventory.0);
+                    states.deep_dive_state.begin_dive(bank, dive_set, &fitted, &states.quest);
+                    states.deep_dive_state.start(&mut shared, deep_dive_data_bank);
+                } else {
+                    states.pod_state.dive_result = Some(String::from("The link to the data bank failed. The key is still good."));
+                    current_state.0 = State::Pod;
+                    states.pod_state.start(&states.quest, &mut shared);
+                }
             },
             (State::Fusiogenic, State::DeepDive) => {
-                states.0.game_progress = states.0.fusiogenic_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
-                current_state.0 = State::DeepDive;
-                states.0.deep_dive_state.level = 0;
-                states.0.deep_dive_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, deep_dive_data_bank);
+                states.fusiogenic_state.close(&mut shared.commands, &mut entity_query);
+                if let Some((bank, dive_set)) = dive_bank(&states.quest) {
+                    current_state.0 = State::DeepDive;
+                    deep_dive_data_bank.0 = bank;
+                    let fitted = cyberware::equipped(&states.quest, &inventory.0);
+                    states.deep_dive_state.begin_dive(bank, dive_set, &fitted, &states.quest);
+                    states.deep_dive_state.start(&mut shared, deep_dive_data_bank);
+                } else {
+                    states.pod_state.dive_result = Some(String::from("The link to the data bank failed. The key is still good."));
+                    current_state.0 = State::Pod;
+                    states.pod_state.start(&states.quest, &mut shared);
+                }
             },
 
 
             (State::Pod, State::Cafe) => {
-                states.0.game_progress = states.0.pod_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.pod_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::Cafe;
-                states.0.cafe_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, -500.0);
+                states.cafe_state.start(&states.quest, &mut shared, -500.0);
             },
 
             (State::DeepDive, State::DeepDive) => {
-                states.0.game_progress = states.0.deep_dive_state.close(&mut commands, &mut entity_query);
-                let mut game_progress = states.0.game_progress.clone();
+                states.deep_dive_state.close(&mut shared.commands, &mut entity_query);
                 current_state.0 = State::DeepDive;
-                states.0.deep_dive_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio, deep_dive_data_bank);
+                states.deep_dive_state.start(&mut shared, deep_dive_data_bank);
+            },
+            (State::DeepDive, State::Pod) if states.deep_dive_state.replaying => {
+                // A replay only shows a run, so nothing in it is paid out or saved.
+                states.deep_dive_state.close(&mut shared.commands, &mut entity_query);
+                current_state.0 = State::Title;
+                states.title_state.start(&mut shared);
+            },
+            (State::DeepDive, State::Pod) if states.deep_dive_state.abandoned => {
+                // Pulled out before the data was reached, so the key is still good.
+                states.deep_dive_state.close(&mut shared.commands, &mut entity_query);
+                states.deep_dive_state.save_replay(false);
+                states.pod_state.dive_result = Some(String::from("You pulled out of the dive. The key is still good."));
+                current_state.0 = State::Pod;
+                states.pod_state.start(&states.quest, &mut shared);
+            },
+            (State::DeepDive, State::Pod) if states.deep_dive_state.traced_out => {
+                // Ejected without the data, so the key is still needed.
+                states.deep_dive_state.close(&mut shared.commands, &mut entity_query);
+                states.deep_dive_state.save_replay(false);
+                let stats = states.deep_dive_state.stats();
+                stats.record(&mut states.quest);
+                states.pod_state.dive_result = Some(format!("TRACE COMPLETE. Security cut the link and the data is lost. {}", dive_record(&states.quest, &stats)));
+                save_game(&states.quest, &inventory.0);
+                current_state.0 = State::Pod;
+                states.pod_state.start(&states.quest, &mut shared);
             },
             (State::DeepDive, State::Pod) => {
-                states.0.game_progress = states.0.deep_dive_state.close(&mut commands, &mut entity_query);
+                states.deep_dive_state.close(&mut shared.commands, &mut entity_query);
+                let mut result = states.deep_dive_state.finish_dive();
+
+                // The key is used up and the data it opened is carried back and paid for.
+                if let Some((key, data)) = inventory.0.data_key() {
+                    let reward = states.deep_dive_state.reward();
+                    inventory.0.take(key, 1);
+                    inventory.0.give(data, 1);
+                    inventory.0.give(CREDITS, reward);
+                    result.push_str(&format!(" +{} credits.", reward));
+                }
+
+                if let Some(owner) = Faction::from_name(&states.deep_dive_state.dive_set.owner) {
+                    reputation::shift(&mut states.quest, owner, -BREACH_COST);
+                }
 
-                let mut dead = false;
+                let stats = states.deep_dive_state.stats();
+                stats.record(&mut states.quest);
+                states.pod_state.dive_result = Some(format!("{} {}", result, dive_record(&states.quest, &stats)));
 
-                match &states.0.game_progress {
-                    GameProgress::GetFirstKey => states.0.game_progress = GameProgress::GetFirstData,
-                    GameProgress::GetSecondKey => states.0.game_progress = GameProgress::GetSecondData,
-                    GameProgress::GetFinalKey => dead = true,
+                // Unless Pip scrubbed it, the final key was the Figure's virus all along.
+                match GameProgress::of(&states.quest) {
+                    GameProgress::GetFirstKey => states.quest.set("first_data"),
+                    GameProgress::GetSecondKey => states.quest.set("second_data"),
+                    GameProgress::GetFinalKey if states.quest.has("key_scrubbed") => states.quest.set("final_data"),
+                    GameProgress::GetFinalKey => states.quest.set("ending_trap"),
                     _ => (),
                 }
 
-                let mut game_progress = states.0.game_progress.clone();
+                save_game(&states.quest, &inventory.0);
 
-                if dead {
+                if ending_for(&states.quest).is_some() {
                     current_state.0 = State::End;
-                    states.0.end_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                    states.end_state.start(&states.quest, &mut shared);
                 } else {
                     current_state.0 = State::Pod;
-                    states.0.pod_state.start(game_progress, &mut commands, &asset_server, &mut texture_atlases, &audio);
+                    states.pod_state.start(&states.quest, &mut shared);
                 }
             },
+
+            (State::Pod, State::Editor) => {
+                states.pod_state.close(&mut shared.commands, &mut entity_query);
+                current_state.0 = State::Editor;
+                states.editor_state.start(&mut shared.commands, &shared.asset_server);
+            },
+            (State::Editor, State::Pod) => {
+                states.editor_state.close(&mut shared.commands, &mut entity_query);
+                current_state.0 = State::Pod;
+                states.pod_state.start(&states.quest, &mut shared);
+            },
+            (State::Editor, State::DeepDive) => {
+                states.editor_state.close(&mut shared.commands, &mut entity_query);
+                current_state.0 = State::DeepDive;
+                let test_map = states.editor_state.map.clone();
+                states.deep_dive_state.begin_test(test_map);
+                states.deep_dive_state.start(&mut shared, deep_dive_data_bank);
+            },
+            (State::DeepDive, State::Editor) => {
+                states.deep_dive_state.close(&mut shared.commands, &mut entity_query);
+                current_state.0 = State::Editor;
+                states.deep_dive_state.test_map = None;
+                states.editor_state.start(&mut shared.commands, &shared.asset_server);
+            },
             _ => ()
         }
     }
 }
 
-fn dive_collision_check(
-    target_player_pos: Vec3,
-    wall_query: &Query<&Transform, (Without<Player>, With<Collider>)>,
-    lava_query: &Query<&Transform, (Without<Player>, With<Lava>)>,
-    data_query: &Query<&Transform, (Without<Player>, With<DataPort>)>,
-    portal_query: &Query<&Transform, (Without<Player>, With<Portal>)>,
-    level: &mut u32,
-    deep_dive_data_bank: &mut ResMut<DeepDiveDataBank>,
-    next_state: &mut ResMut<NextState>,
-) -> bool {
-
-    for portal_trans in portal_query.iter() {
-        let collision = collide(
-            target_player_pos,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-            portal_trans.translation,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-        );
-        if collision.is_some() {
-            if deep_dive_data_bank.0 < 1 {
-                deep_dive_data_bank.0 += 1;
+fn spawn_dive_tiles(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    map: &DiveMap,
+    hidden: &[Position],
+) {
+    // Every tile kind is one column of the tileset, with its frames going down.
+    let tileset_handle = asset_server.load("textures/dive_tiles.png");
+    let mut tile_strip = |column: f32, frames: usize| {
+        texture_atlases.add(TextureAtlas::from_grid(
+            tileset_handle.clone(),
+            Vec2::splat(DIVE_TILESET_SIZE),
+            1,
+            frames,
+            None,
+            Some(Vec2::new(column * DIVE_TILESET_SIZE, 0.0)),
+        ))
+    };
+    let wall_atlas = tile_strip(0.0, 16);
+    let lava_atlas = tile_strip(1.0, 4);
+    let data_port_atlas = tile_strip(2.0, 4);
+    let portal_atlas = tile_strip(3.0, 4);
+    let conveyor_atlas = tile_strip(4.0, 4);
+    let stopper_atlas = tile_strip(5.0, 1);
+    let fragment_atlas = tile_strip(6.0, 4);
+
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            let (texture_atlas, index, animated) = match map.tile((x, y)) {
+                Tile::Floor => continue,
+                Tile::Wall => (wall_atlas.clone(), map.wall_mask((x, y)), false),
+                Tile::Lava => (lava_atlas.clone(), 0, true),
+                Tile::DataPort => (data_port_atlas.clone(), 0, true),
+                Tile::Portal => (portal_atlas.clone(), 0, true),
+                Tile::Conveyor(direction) => {
+                    let index = match direction {
+                        Direction::Left => 0,
+                        Direction::Right => 1,
+                        Direction::Up => 2,
+                        Direction::Down => 3,
+                    };
+                    (conveyor_atlas.clone(), index, false)
+                },
+                Tile::Stopper => (stopper_atlas.clone(), 0, false),
+                Tile::Fragment => (fragment_atlas.clone(), 0, true),
+            };
+
+            let mut transform = Transform::from_scale(Vec3::splat(DEEP_DIVE_TILE_SCALE / DIVE_TILESET_SIZE));
+            transform.translation = dive_tile_translation(map, (x, y));
+
+            let tile = commands.spawn((
+                SpriteSheetBundle {
+                    texture_atlas,
+                    sprite: TextureAtlasSprite::new(index),
+                    transform,
+                    visibility: Visibility { is_visible: !hidden.contains(&(x, y)) },
+                    ..default()
+                },
+                DiveTile,
+                DiveTileAt((x, y)),
+            )).id();
+
+            if animated {
+                commands.entity(tile).insert((
+                    AnimationTimer(Timer::from_seconds(0.2, TimerMode::Repeating)),
+                    AnimationMode(1),
+                ));
             }
-            next_state.0 = State::Pod;
         }
     }
+}
 
-    for data_trans in data_query.iter() {
-        let collision = collide(
-            target_player_pos,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-            data_trans.translation,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-        );
-        if collision.is_some() {
-            if *level < 2 {
-                *level += 1;
-            }
-            next_state.0 = State::DeepDive;
-        }
-    }
+// Maps are centered on each axis, with the first line of the file on top.
+fn dive_tile_translation(map: &DiveMap, (x, y): Position) -> Vec3 {
+    let half_width = map.width as i32 / 2;
+    let half_height = map.height as i32 / 2;
 
-    for lava_trans in lava_query.iter() {
-        let collision = collide(
-            target_player_pos,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-            lava_trans.translation,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-        );
-        if collision.is_some() {
-            next_state.0 = State::DeepDive;
-        }
-    }
+    Vec3::new((x - half_width) as f32 * DEEP_DIVE_TILE_SCALE, (half_height - y) as f32 * DEEP_DIVE_TILE_SCALE, 0.0)
+}
 
-    for wall_trans in wall_query.iter() {
-        let collision = collide(
-            target_player_pos,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-            wall_trans.translation,
-            Vec2::splat(DEEP_DIVE_TILE_SCALE),
-        );
-        if collision.is_some() {
-            return false;
+fn dive_tile_at(map: &DiveMap, world: Vec2) -> Position {
+    let half_width = map.width as i32 / 2;
+    let half_height = map.height as i32 / 2;
+
+    ((world.x / DEEP_DIVE_TILE_SCALE).round() as i32 + half_width, half_height - (world.y / DEEP_DIVE_TILE_SCALE).round() as i32)
+}
+
+// Keeps the focus in view on maps bigger than the window without showing
+// past the map edges. Maps that fit are simply centered.
+fn dive_camera_target(map: &DiveMap, focus: Vec3, view: Vec2) -> Vec2 {
+    let top_left = dive_tile_translation(map, (0, 0));
+    let bottom_right = dive_tile_translation(map, (map.width as i32 - 1, map.height as i32 - 1));
+
+    let min = Vec2::new(top_left.x, bottom_right.y) - DEEP_DIVE_TILE_SCALE / 2.0;
+    let max = Vec2::new(bottom_right.x, top_left.y) + DEEP_DIVE_TILE_SCALE / 2.0;
+
+    let follow = |focus: f32, min: f32, max: f32, view: f32| {
+        if max - min <= view {
+            (min + max) / 2.0
+        } else {
+            focus.clamp(min + view / 2.0, max - view / 2.0)
         }
-    }
+    };
 
-    true
+    Vec2::new(follow(focus.x, min.x, max.x, view.x), follow(focus.y, min.y, max.y, view.y))
+}
+
+fn read_dive_input(
+    keyboard_input: &Res<Input<KeyCode>>,
+) -> Option<DiveInput> {
+    if keyboard_input.just_pressed(KeyCode::A) {
+        Some(DiveInput::Slide(Direction::Left))
+    } else if keyboard_input.just_pressed(KeyCode::W) {
+        Some(DiveInput::Slide(Direction::Up))
+    } else if keyboard_input.just_pressed(KeyCode::S) {
+        Some(DiveInput::Slide(Direction::Down))
+    } else if keyboard_input.just_pressed(KeyCode::D) {
+        Some(DiveInput::Slide(Direction::Right))
+    } else if keyboard_input.just_pressed(KeyCode::Z) {
+        Some(DiveInput::Undo)
+    } else if keyboard_input.just_pressed(KeyCode::B) {
+        Some(DiveInput::Brake)
+    } else if keyboard_input.just_pressed(KeyCode::X) {
+        Some(DiveInput::Breach)
+    } else {
+        None
+    }
 }
 
 fn npc_collision_check(
@@ -2616,62 +4670,171 @@ fn spawn_robot(
     asset_server: &Res<AssetServer>,
     texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
     x_offset: f32,
+    talking_id: i32,
 ) {
     let robot_texture_handle = asset_server.load("textures/robot.png");
     let robot_texture_atlas =
         TextureAtlas::from_grid(robot_texture_handle, Vec2::new(8.0, 8.0), 1, 4, None, None);
     let robot_texture_atlas_handle = texture_atlases.add(robot_texture_atlas);
 
-    let mut robot_transform = Transform::from_scale(Vec3::splat(5.0));
-    robot_transform.translation.x = x_offset;
-    robot_transform.translation.y = 10.0;
-    robot_transform.translation.z = 100.0;
-    let mut robot_anim_timer = Timer::from_seconds(0.1, TimerMode::Repeating);
+    let mut robot_transform = Transform::from_scale(Vec3::splat(5.0));
+    robot_transform.translation.x = x_offset;
+    robot_transform.translation.y = 10.0;
+    robot_transform.translation.z = 100.0;
+    let mut robot_anim_timer = Timer::from_seconds(0.1, TimerMode::Repeating);
+
+    commands.spawn((
+        SpriteSheetBundle {
+            texture_atlas: robot_texture_atlas_handle,
+            transform: robot_transform,
+            ..default()
+        },
+        AnimationTimer(robot_anim_timer),
+        AnimationMode(1),
+        NPC {
+            talking_id
+        }
+    ));
+}
+
+fn spawn_figure(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    x_offset: f32,
+    talking_id: i32,
+) {
+    let figure_texture_handle = asset_server.load("textures/figure.png");
+    let figure_texture_atlas =
+        TextureAtlas::from_grid(figure_texture_handle, Vec2::new(15.0, 32.0), 1, 24, None, None);
+    let figure_texture_atlas_handle = texture_atlases.add(figure_texture_atlas);
+
+    let mut figure_transform = Transform::from_scale(Vec3::splat(5.0));
+    figure_transform.translation.x = x_offset;
+    figure_transform.translation.z = 80.0;
+    let mut figure_anim_timer = Timer::from_seconds(0.1, TimerMode::Repeating);
+
+    commands.spawn((
+        SpriteSheetBundle {
+            texture_atlas: figure_texture_atlas_handle,
+            transform: figure_transform,
+            ..default()
+        },
+        AnimationTimer(figure_anim_timer),
+        AnimationMode(1),
+        NPC {
+            talking_id
+        }
+    ));
+}
+
+fn spawn_shopkeeper(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    x_offset: f32,
+    talking_id: i32,
+) {
+    let shopkeeper_texture_handle = asset_server.load("textures/shopkeeper.png");
+    let shopkeeper_texture_atlas =
+        TextureAtlas::from_grid(shopkeeper_texture_handle, Vec2::new(16.0, 32.0), 1, 4, None, None);
+    let shopkeeper_texture_atlas_handle = texture_atlases.add(shopkeeper_texture_atlas);
+
+    let mut shopkeeper_transform = Transform::from_scale(Vec3::splat(5.0));
+    shopkeeper_transform.translation.x = x_offset;
+    shopkeeper_transform.translation.z = 80.0;
+    let shopkeeper_anim_timer = Timer::from_seconds(0.3, TimerMode::Repeating);
 
     commands.spawn((
         SpriteSheetBundle {
-            texture_atlas: robot_texture_atlas_handle,
-            transform: robot_transform,
+            texture_atlas: shopkeeper_texture_atlas_handle,
+            transform: shopkeeper_transform,
             ..default()
         },
-        AnimationTimer(robot_anim_timer),
+        AnimationTimer(shopkeeper_anim_timer),
         AnimationMode(1),
         NPC {
-            talking_id: 1
+            talking_id
         }
     ));
 }
 
-fn spawn_figure(
+// The barista and the patrons share one sprite layout, `textures/<name>.png`
+// at 16x32 with four idle frames.
+fn spawn_townsperson(
     commands: &mut Commands,
     asset_server: &Res<AssetServer>,
     texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    name: &str,
     x_offset: f32,
+    talking_id: i32,
 ) {
-    let figure_texture_handle = asset_server.load("textures/figure.png");
-    let figure_texture_atlas =
-        TextureAtlas::from_grid(figure_texture_handle, Vec2::new(15.0, 32.0), 1, 24, None, None);
-    let figure_texture_atlas_handle = texture_atlases.add(figure_texture_atlas);
+    let npc_texture_handle = asset_server.load(format!("textures/{}.png", name));
+    let npc_texture_atlas =
+        TextureAtlas::from_grid(npc_texture_handle, Vec2::new(16.0, 32.0), 1, 4, None, None);
+    let npc_texture_atlas_handle = texture_atlases.add(npc_texture_atlas);
 
-    let mut figure_transform = Transform::from_scale(Vec3::splat(5.0));
-    figure_transform.translation.x = x_offset;
-    figure_transform.translation.z = 80.0;
-    let mut figure_anim_timer = Timer::from_seconds(0.1, TimerMode::Repeating);
+    let mut npc_transform = Transform::from_scale(Vec3::splat(5.0));
+    npc_transform.translation.x = x_offset;
+    npc_transform.translation.z = 80.0;
+    let npc_anim_timer = Timer::from_seconds(0.3, TimerMode::Repeating);
 
     commands.spawn((
         SpriteSheetBundle {
-            texture_atlas: figure_texture_atlas_handle,
-            transform: figure_transform,
+            texture_atlas: npc_texture_atlas_handle,
+            transform: npc_transform,
             ..default()
         },
-        AnimationTimer(figure_anim_timer),
+        AnimationTimer(npc_anim_timer),
         AnimationMode(1),
         NPC {
-            talking_id: 2
+            talking_id
         }
     ));
 }
 
+// Spawns whoever the placement file puts in the room now, and says how many.
+fn spawn_npcs(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    state: State,
+    quest: &Quest,
+) -> usize {
+    let placements = Placement::load_all(NPCS_PATH).unwrap_or_else(|e| {
+        warn!("Cannot open NPC placements: {}", e);
+        Vec::new()
+    });
+    let step = progress_key(&GameProgress::of(quest));
+
+    let mut spawned = 0;
+    for placement in npc::present(&placements, room_name(state), quest, step) {
+        let talking_id = match NPC_KINDS.iter().find(|(name, ..)| *name == placement.npc) {
+            Some((_, _, talking_id)) => *talking_id,
+            None => {
+                warn!("Unknown NPC: {}", placement.npc);
+                continue;
+            },
+        };
+
+        match placement.npc.as_str() {
+            "robot" => spawn_robot(commands, asset_server, texture_atlases, placement.x, talking_id),
+            "figure" => spawn_figure(commands, asset_server, texture_atlases, placement.x, talking_id),
+            "shopkeeper" => spawn_shopkeeper(commands, asset_server, texture_atlases, placement.x, talking_id),
+            name => spawn_townsperson(commands, asset_server, texture_atlases, name, placement.x, talking_id),
+        }
+        spawned += 1;
+    }
+
+    spawned
+}
+
+fn npc_name(talking_id: i32) -> &'static str {
+    NPC_KINDS.iter()
+        .find(|(_, _, id)| *id == talking_id)
+        .map_or("Stranger", |(_, name, _)| *name)
+}
+
 fn spawn_background(
     commands: &mut Commands,
     asset_server: &Res<AssetServer>,
@@ -2737,15 +4900,7 @@ fn spawn_talking_entity (
     talking_transform.translation.y = -250.0;
     talking_transform.translation.z = 250.0;
 
-    let talking_texture;
-
-    if current_talking == 0 {
-        talking_texture = asset_server.load("textures/talking0.png")
-    } else if current_talking == 1 {
-        talking_texture = asset_server.load("textures/talking1.png")
-    } else {
-        talking_texture = asset_server.load("textures/talking2.png")
-    }
+    let talking_texture = asset_server.load(format!("textures/talking{}.png", current_talking));
 
     let talking_entity = commands.spawn(SpriteBundle {
         sprite: Sprite {
@@ -2763,9 +4918,11 @@ fn spawn_talking_entity (
 fn update_msg(
     current_msg: &String,
     text: &mut Text,
+    settings: &Settings,
 ) -> bool {
-    if text.sections[0].value.len() < current_msg.len() {
-        text.sections[0].value = current_msg[..text.sections[0].value.len() + 1].to_string();
+    let msg = settings.show_keys(current_msg);
+    if text.sections[0].value.len() < msg.len() {
+        text.sections[0].value = msg[..typed_len(text.sections[0].value.len(), msg.len(), settings.text_speed)].to_string();
         return false;
     }
 
@@ -2785,7 +4942,7 @@ fn manage_dialog(
     commands: &mut Commands,
     asset_server: &Res<AssetServer>,
     state: State,
-    game_progress: GameProgress,
+    folder: &str,
     current_talking: u32,
 
     mut talking_entity: Entity,
@@ -2797,30 +4954,10 @@ fn manage_dialog(
     current_msg: &mut String,
 ) -> (u32, usize, Entity) {
 
-    let state_str = match state {
-        State::TechShop => "tech_shop",
-        State::Alleyway => "alleyway",
-        State::Cyberway => "cyberway",
-        State::PartsShop => "parts_shop",
-        State::Cafe => "cafe",
-        State::Pod => "pod",
-        State::Alleyway2 => "alleyway2",
-        _ => "PANIC",
-    };
+    let state_str = room_name(state);
 
-    let folder_str = match game_progress {
-        GameProgress::Start => "start",
-        GameProgress::TalkToFigure => "talk",
-        GameProgress::GetFirstKey => "first_key",
-        GameProgress::GetFirstData => "first_data",
-        GameProgress::GetSecondKey => "second_key",
-        GameProgress::GetSecondData => "second_data",
-        GameProgress::GetFinalKey => "final_key",
-        GameProgress::GetFinalData => "final_data",
-    };
-   
     if dialog_texts.len() == 0 {
-        let file_path = format!("assets/texts/{}/{}/dialog{}-{}.txt", state_str, folder_str, current_talking, dialog_state);
+        let file_path = dialog_path(state_str, folder, current_talking, dialog_state);
         
         let raw_text = fs::read_to_string(file_path).expect("Cannot open dialog text!");
         let lines = raw_text.split('\n');
@@ -2849,3 +4986,572 @@ fn manage_dialog(
 
     (dialog_state, dialog_line, talking_entity)
 }
+
+// Folder name of a hub room, for its dialog and NPC placements.
+fn room_name(state: State) -> &'static str {
+    match state {
+        State::TechShop => "tech_shop",
+        State::Alleyway => "alleyway",
+        State::Cyberway => "cyberway",
+        State::PartsShop => "parts_shop",
+        State::Cafe => "cafe",
+        State::Pod => "pod",
+        State::Alleyway2 => "alleyway2",
+        _ => "PANIC",
+    }
+}
+
+// An NPC's lines come from the folder of a side quest stage when there are
+// any, then from the current story step's, then from one named for a
+// corporation's standing (see `reputation::standing_flags`), else from the
+// latest earlier step that has some, so lines only need writing where they
+// change and standing never hides what a step has to say.
+fn dialog_folder(room: &str, quest: &Quest, npc: u32) -> String {
+    let file = format!("dialog{}-0.txt", npc);
+    let progress = GameProgress::of(quest);
+
+    let stages = DIALOG_FLAG_CHAINS.iter()
+        .filter_map(|chain| chain.iter().rev().find(|flag| quest.has(flag)).copied())
+        .map(String::from);
+    let standings = reputation::standing_flags(quest);
+    let earlier_steps = STORY.iter().rev()
+        .skip_while(|step| **step != progress)
+        .skip(1)
+        .map(|step| progress_key(step).to_string());
+
+    stages.chain([progress_key(&progress).to_string()]).chain(standings).chain(earlier_steps)
+        .find(|folder| Path::new(&format!("assets/texts/{}/{}/{}", room, folder, file)).exists())
+        .unwrap_or_else(|| progress_key(&progress).to_string())
+}
+
+// Later talks read `dialog<npc>-1.txt`, or the first talk again where a
+// folder has nothing new to say.
+fn dialog_path(room: &str, folder: &str, npc: u32, dialog_state: u32) -> String {
+    let path = format!("assets/texts/{}/{}/dialog{}-{}.txt", room, folder, npc, dialog_state);
+    if Path::new(&path).exists() {
+        path
+    } else {
+        format!("assets/texts/{}/{}/dialog{}-0.txt", room, folder, npc)
+    }
+}
+
+// A choice file only counts in the folder the talk itself came from (see
+// `dialog_folder`), and only until one of its options has been picked.
+fn open_choices(room: &str, folder: &str, quest: &Quest, npc: u32) -> Vec<Choice> {
+    let choices = Choice::load_all(format!("assets/texts/{}/{}/choice{}.txt", room, folder, npc)).unwrap_or_default();
+
+    if choices.iter().any(|choice| quest.has(&choice.flag)) {
+        Vec::new()
+    } else {
+        choices
+    }
+}
+
+// Sets a flag picked from a choice menu, with the standing it shifts.
+fn pick_choice(quest: &mut Quest, flag: &str) {
+    let shifts = Shift::load_all(REPUTATION_PATH).unwrap_or_else(|e| {
+        warn!("Cannot open reputation shifts: {}", e);
+        Vec::new()
+    });
+
+    reputation::pick(quest, &shifts, flag);
+}
+
+fn ending_for(quest: &Quest) -> Option<Ending> {
+    let endings = Ending::load_all(ENDINGS_PATH).unwrap_or_else(|e| {
+        warn!("Cannot open endings: {}", e);
+        Vec::new()
+    });
+
+    story::ending_for(&endings, quest).cloned()
+}
+
+// The coffee run for the Lycia Cafe barista, moved on at the end of a talk.
+// Returns what the player got, if anything.
+fn advance_coffee_run(state: State, npc: u32, quest: &mut Quest, inventory: &mut Inventory) -> Option<String> {
+    let speaker = NPC_KINDS.iter()
+        .find(|(_, _, talking_id)| *talking_id as u32 == npc)
+        .map(|(name, ..)| *name);
+
+    match (state, speaker) {
+        (State::Cafe, Some("barista")) if !quest.has("coffee_offered") => {
+            quest.set("coffee_offered");
+            inventory.give("coffee", 1);
+            Some(String::from("Got a Synth-Espresso for Pip."))
+        },
+        (State::Cafe, Some("barista")) if quest.has("coffee_delivered") && !quest.has("coffee_paid") => {
+            quest.set("coffee_paid");
+            inventory.give(CREDITS, COFFEE_REWARD);
+            Some(format!("Got {} credits.", COFFEE_REWARD))
+        },
+        (State::TechShop, Some("robot")) if !quest.has("coffee_delivered") && inventory.take("coffee", 1) => {
+            quest.set("coffee_delivered");
+            Some(String::from("Pip has the coffee."))
+        },
+        _ => None,
+    }
+}
+
+// Folder, dive set and quest flag name of each progress step.
+fn progress_key(game_progress: &GameProgress) -> &'static str {
+    match game_progress {
+        GameProgress::Start => "start",
+        GameProgress::TalkToFigure => "talk",
+        GameProgress::GetFirstKey => "first_key",
+        GameProgress::GetFirstData => "first_data",
+        GameProgress::GetSecondKey => "second_key",
+        GameProgress::GetSecondData => "second_data",
+        GameProgress::GetFinalKey => "final_key",
+        GameProgress::GetFinalData => "final_data",
+    }
+}
+
+// Index of the dive set listed for the current progress step.
+fn dive_bank(quest: &Quest) -> Option<(u32, DiveSet)> {
+    let key = progress_key(&GameProgress::of(quest));
+    let sets = match DiveSet::load_all(DIVE_SETS_PATH) {
+        Ok(sets) => sets,
+        Err(e) => {
+            warn!("Cannot open dive sets: {}", e);
+            return None;
+        },
+    };
+
+    let bank = sets.iter().position(|set| set.key == key);
+    if bank.is_none() {
+        warn!("No dive for {}.", key);
+    }
+    bank.map(|bank| (bank as u32, sets[bank].clone()))
+}
+
+// The first lore entry past `told` that the found fragments unlock. Entries
+// are files named by the fragments they need.
+fn next_lore(told: u32, fragments: u32) -> Option<(u32, Vec<String>)> {
+    let mut unlocked: Vec<u32> = fs::read_dir("assets/texts/alleyway2/lore").ok()?
+        .filter_map(|entry| entry.ok()?.path().file_stem()?.to_str()?.parse().ok())
+        .filter(|needed| *needed > told && *needed <= fragments)
+        .collect();
+    unlocked.sort();
+
+    let needed = *unlocked.first()?;
+    let raw_text = fs::read_to_string(format!("assets/texts/alleyway2/lore/{}.txt", needed)).ok()?;
+
+    Some((needed, raw_text.split('\n').map(String::from).collect()))
+}
+
+// A text panel in the top half of the screen for in-room menus.
+fn spawn_menu(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    settings: &Settings,
+    menu_text: String,
+) -> Entity {
+    let font = asset_server.load("fonts/PressStart2P-Regular.ttf");
+    let text_style = TextStyle {
+        font,
+        font_size: 14.0,
+        color: Color::WHITE,
+    };
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(20.0),
+                top: Val::Percent(6.0),
+                ..default()
+            },
+            size: Size::new(Val::Percent(60.0), Val::Auto),
+            padding: UiRect::all(Val::Px(20.0)),
+            ..default()
+        },
+        background_color: Color::rgba(0.0, 0.05, 0.05, 0.9).into(),
+        z_index: ZIndex::Global(5),
+        ..default()
+    }).with_children(|parent| {
+        parent.spawn(TextBundle::from_section(settings.show_keys(&menu_text), text_style));
+    }).id()
+}
+
+// A line for the load menu: how far the story got and the credits on hand.
+fn save_summary(path: &str) -> String {
+    let saved = match SaveGame::load(path) {
+        Ok(saved) => saved,
+        Err(_) => return String::from("(empty)"),
+    };
+
+    if ending_for(&saved.quest).is_some() {
+        return String::from("(story over)");
+    }
+
+    let progress = GameProgress::of(&saved.quest);
+    let step = STORY.iter().position(|step| *step == progress).unwrap_or(0) + 1;
+    format!("Step {}/{}  {} credits", step, STORY.len(), saved.inventory.count(CREDITS))
+}
+
+// Everything on the fixed step holds still while the pause menu is up.
+fn unless_paused(
+    In(should_run): In<ShouldRun>,
+    pause_menu: Res<PauseMenu>,
+    settings_menu: Res<SettingsMenu>,
+) -> ShouldRun {
+    if pause_menu.open || settings_menu.open {
+        ShouldRun::No
+    } else {
+        should_run
+    }
+}
+
+// What the pause menu offers in the current state.
+fn pause_options(state: &State, deep_dive_state: &DeepDiveState) -> &'static [&'static str] {
+    match state {
+        State::DeepDive if deep_dive_state.replaying => &REPLAY_PAUSE_OPTIONS,
+        State::DeepDive => &DIVE_PAUSE_OPTIONS,
+        _ => &HUB_PAUSE_OPTIONS,
+    }
+}
+
+// [Esc] in the rooms and dives. Runs every frame, outside the fixed step it freezes.
+fn run_pause_menu(
+    shared: SharedResources,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    current_state: Res<CurrentState>,
+    mut next_state: ResMut<NextState>,
+    mut states: ResMut<StateData>,
+    inventory: Res<PlayerInventory>,
+    (mut pause_menu, mut settings_menu): (ResMut<PauseMenu>, ResMut<SettingsMenu>),
+) {
+    let SharedResources { mut commands, asset_server, settings, .. } = shared;
+
+    let pause_options = pause_options(&current_state.0, &states.0.deep_dive_state);
+    match current_state.0 {
+        State::TechShop | State::Alleyway | State::Cyberway | State::PartsShop | State::Cafe | State::Pod | State::Alleyway2 | State::DeepDive => (),
+        _ => return,
+    }
+
+    if settings_menu.open {
+        return;
+    }
+    // Back from the settings screen.
+    if pause_menu.open && pause_menu.entity.is_none() {
+        pause_menu.redraw(&mut commands, &asset_server, &settings.0, pause_options);
+    }
+
+    if !pause_menu.open {
+        if keyboard_input.just_pressed(KeyCode::Escape) && !states.0.has_escape(&current_state.0) {
+            pause_menu.open = true;
+            pause_menu.index = 0;
+            pause_menu.msg = String::from("");
+            pause_menu.saving = false;
+            pause_menu.redraw(&mut commands, &asset_server, &settings.0, pause_options);
+        }
+        return;
+    }
+
+    let options = if pause_menu.saving { SAVE_SLOTS } else { pause_options.len() };
+
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        pause_menu.index = (pause_menu.index + options - 1) % options;
+    } else if keyboard_input.just_pressed(KeyCode::Down) {
+        pause_menu.index = (pause_menu.index + 1) % options;
+    } else if keyboard_input.just_pressed(KeyCode::Escape) && pause_menu.saving {
+        pause_menu.saving = false;
+        pause_menu.index = pause_options.iter().position(|option| *option == "Save").unwrap_or(0);
+        pause_menu.msg = String::from("");
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        pause_menu.close(&mut commands);
+    } else if keyboard_input.just_pressed(KeyCode::Space) && pause_menu.saving {
+        let saved = SaveGame {
+            quest: states.0.quest.clone(),
+            inventory: inventory.0.clone(),
+        };
+        pause_menu.msg = match saved.save(save::slot_path(pause_menu.index)) {
+            Ok(()) => format!("Saved to slot {}.", pause_menu.index + 1),
+            Err(e) => format!("Cannot save: {}", e),
+        };
+    } else if keyboard_input.just_pressed(KeyCode::Space) {
+        match pause_options[pause_menu.index] {
+            "Resume" => pause_menu.close(&mut commands),
+            "Save" => {
+                pause_menu.saving = true;
+                pause_menu.index = 0;
+                pause_menu.msg = String::from("");
+            },
+            "Settings" => {
+                if let Some(entity) = pause_menu.entity.take() {
+                    commands.entity(entity).despawn_recursive();
+                }
+                settings_menu.open(&mut commands, &asset_server, &settings.0);
+            },
+            "Restart Level" => {
+                states.0.deep_dive_state.restart_level();
+                next_state.0 = State::DeepDive;
+                pause_menu.close(&mut commands);
+            },
+            "Abandon Dive" if states.0.deep_dive_state.test_map.is_some() => {
+                next_state.0 = State::Editor;
+                pause_menu.close(&mut commands);
+            },
+            "Abandon Dive" => {
+                states.0.deep_dive_state.abandoned = true;
+                next_state.0 = State::Pod;
+                pause_menu.close(&mut commands);
+            },
+            _ => {
+                next_state.0 = State::Title;
+                pause_menu.close(&mut commands);
+            },
+        }
+    } else {
+        return;
+    }
+
+    // The keys that worked the menu are not for the room underneath.
+    keyboard_input.clear();
+
+    if pause_menu.open && !settings_menu.open {
+        pause_menu.redraw(&mut commands, &asset_server, &settings.0, pause_options);
+    }
+}
+
+// Opened from the title and pause menus. Changes apply at once and are
+// written to the config file when the screen closes.
+fn run_settings_menu(
+    shared: SharedResources,
+    (mut keyboard_input, physical_keys): (ResMut<Input<KeyCode>>, Res<PhysicalKeys>),
+    current_state: Res<CurrentState>,
+    mut states: ResMut<StateData>,
+    mut settings_menu: ResMut<SettingsMenu>,
+    mut windows: ResMut<Windows>,
+    audio_sinks: Res<Assets<AudioSink>>,
+) {
+    let SharedResources { mut commands, asset_server, mut settings, music, .. } = shared;
+
+    if !settings_menu.open {
+        if matches!(current_state.0, State::Title) && states.0.title_state.opening_settings {
+            states.0.title_state.opening_settings = false;
+            settings_menu.open(&mut commands, &asset_server, &settings.0);
+        }
+        return;
+    }
+
+    if let Some(action) = settings_menu.binding {
+        // The bindings are in effect, so the new key is read off the real keyboard.
+        if physical_keys.0.just_pressed(KeyCode::Escape) {
+            settings_menu.binding = None;
+        } else if let Some(key) = BINDABLE_KEYS.iter().find(|key| physical_keys.0.just_pressed(**key)) {
+            let key = format!("{:?}", key);
+            if settings.0.bind(ACTIONS[action].0, &key) {
+                settings_menu.binding = None;
+            } else {
+                settings_menu.msg = format!("[{}] would work the menus twice.", key);
+            }
+        } else {
+            return;
+        }
+    } else if keyboard_input.just_pressed(KeyCode::Up) {
+        settings_menu.index = (settings_menu.index + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+    } else if keyboard_input.just_pressed(KeyCode::Down) {
+        settings_menu.index = (settings_menu.index + 1) % SETTINGS_ROWS;
+    } else if keyboard_input.just_pressed(KeyCode::Left) {
+        settings_menu.adjust(&mut settings.0, -1);
+    } else if keyboard_input.just_pressed(KeyCode::Right) {
+        settings_menu.adjust(&mut settings.0, 1);
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        settings_menu.close(&mut commands, &settings.0);
+    } else if keyboard_input.just_pressed(KeyCode::Space) {
+        match settings_menu.index {
+            index if index == SETTINGS_ROWS - 1 => settings_menu.close(&mut commands, &settings.0),
+            index if index >= SETTINGS_VALUES => {
+                settings_menu.binding = Some(index - SETTINGS_VALUES);
+                settings_menu.msg = String::from("");
+            },
+            _ => settings_menu.adjust(&mut settings.0, 1),
+        }
+    } else {
+        return;
+    }
+
+    apply_window_settings(&settings.0, &mut windows);
+    if let Some(sink) = music.0.as_ref().and_then(|music| audio_sinks.get(music)) {
+        sink.set_volume(settings.0.music_volume);
+    }
+    keyboard_input.clear();
+
+    if settings_menu.open {
+        settings_menu.redraw(&mut commands, &asset_server, &settings.0);
+    }
+}
+
+fn load_settings() -> Settings {
+    let path = match settings::config_path() {
+        Some(path) => path,
+        None => return Settings::default(),
+    };
+
+    match Settings::load(&path) {
+        Ok(settings) => settings,
+        // No file yet on the first run.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Settings::default(),
+        Err(e) => {
+            warn!("Cannot load settings {}: {}", path.display(), e);
+            Settings::default()
+        }
+    }
+}
+
+fn setup_window(
+    settings: Res<GameSettings>,
+    mut windows: ResMut<Windows>,
+) {
+    apply_window_settings(&settings.0, &mut windows);
+}
+
+fn apply_window_settings(settings: &Settings, windows: &mut Windows) {
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+
+    // The layout is fixed, so the scale setting is the only way to resize.
+    window.set_resizable(false);
+    window.set_resolution(WINDOW_WIDTH, WINDOW_HEIGHT);
+    window.set_scale_factor_override(Some(window.backend_scale_factor() * settings.window_scale as f64));
+    window.set_mode(if settings.fullscreen { WindowMode::BorderlessFullscreen } else { WindowMode::Windowed });
+}
+
+// Bound keys stand in for the game's own: the game reads the remapped keyboard
+// for a frame, and the real one goes back before the next input update.
+fn remap_keys(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut physical_keys: ResMut<PhysicalKeys>,
+    settings: Res<GameSettings>,
+    current_state: Res<CurrentState>,
+) {
+    physical_keys.0 = keyboard_input.clone();
+
+    // The editor keeps its own keys.
+    if settings.0.has_default_keys() || matches!(current_state.0, State::Editor) {
+        return;
+    }
+
+    let game_keys = |key: &KeyCode| -> Vec<KeyCode> {
+        let name = format!("{:?}", key);
+        settings.0.game_keys(&name).iter()
+            .filter_map(|game_key| if *game_key == name { Some(*key) } else { BINDABLE_KEYS.into_iter().find(|code| format!("{:?}", code) == *game_key) })
+            .collect()
+    };
+
+    let mut remapped = Input::default();
+    for key in physical_keys.0.get_pressed() {
+        for game_key in game_keys(key) {
+            remapped.press(game_key);
+        }
+    }
+    for key in physical_keys.0.get_just_released() {
+        for game_key in game_keys(key) {
+            remapped.press(game_key);
+            remapped.release(game_key);
+        }
+    }
+
+    // Pressing sets just_pressed, which only the keys hit this frame keep.
+    let just_pressed: Vec<KeyCode> = physical_keys.0.get_just_pressed().flat_map(game_keys).collect();
+    let held: Vec<KeyCode> = remapped.get_just_pressed().copied().collect();
+    for key in held {
+        if !just_pressed.contains(&key) {
+            remapped.clear_just_pressed(key);
+        }
+    }
+
+    *keyboard_input = remapped;
+}
+
+fn restore_keys(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    physical_keys: Res<PhysicalKeys>,
+) {
+    *keyboard_input = physical_keys.0.clone();
+}
+
+fn quit_from_title(
+    current_state: Res<CurrentState>,
+    states: Res<StateData>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    if matches!(current_state.0, State::Title) && states.0.title_state.quitting {
+        app_exit.send(AppExit);
+    }
+}
+
+fn save_game(quest: &Quest, inventory: &Inventory) {
+    let save = SaveGame {
+        quest: quest.clone(),
+        inventory: inventory.clone(),
+    };
+
+    if let Err(e) = save.save(SAVE_PATH) {
+        warn!("Cannot save the game: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::AssetPlugin;
+
+    use super::*;
+
+    // Bank 0 played through by the solver, as if it had been recorded.
+    fn solved_replay() -> Replay {
+        let sets = DiveSet::load_all(DIVE_SETS_PATH).unwrap();
+        let mut inputs = Vec::new();
+
+        for level in 0..sets[0].levels.len() {
+            let map = DiveMap::load(sets[0].map_path(level).unwrap()).unwrap();
+            let solution = dive::solve(&map).unwrap();
+            inputs.extend(solution.moves.into_iter().map(DiveInput::Slide));
+        }
+
+        Replay { bank: 0, inputs, ..Replay::default() }
+    }
+
+    #[test]
+    fn watching_a_replay_leaves_the_save_alone() {
+        let saved = fs::read_to_string(SAVE_PATH).ok();
+
+        let mut state_collection = StateCollection::new();
+        assert!(state_collection.deep_dive_state.begin_playback(solved_replay()));
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<TextureAtlas>()
+        .init_resource::<Audio>()
+        .init_resource::<Input<KeyCode>>()
+        .init_resource::<Input<MouseButton>>()
+        .init_resource::<Windows>()
+        .insert_resource(CurrentState(State::DeepDive))
+        .insert_resource(NextState(State::Title))
+        .insert_resource(StateData(state_collection))
+        .insert_resource(DeepDiveDataBank(0))
+        .insert_resource(PlayerInventory(Inventory::new()))
+        .insert_resource(GameSettings(Settings::default()))
+        .insert_resource(Music::default())
+        .add_startup_system(start_initial_state)
+        .add_system(run_current_game_state)
+        .add_system(manage_state_changes.before(run_current_game_state));
+
+        let mut reached_data = false;
+        for _ in 0..20_000 {
+            app.update();
+            reached_data |= matches!(app.world.resource::<NextState>().0, State::Pod);
+            if matches!(app.world.resource::<CurrentState>().0, State::Title) {
+                break;
+            }
+        }
+
+        assert!(reached_data, "The replay never got to the data");
+        assert!(matches!(app.world.resource::<CurrentState>().0, State::Title));
+        assert_eq!(fs::read_to_string(SAVE_PATH).ok(), saved);
+    }
+}
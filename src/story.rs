@@ -0,0 +1,140 @@
+// Dialog choices and the endings they lead to.
+//
+// A choice file sits in a dialog folder next to the lines it follows, one
+// option per line: `<flag> <text>`. Picking an option sets its flag. The
+// endings file lists `<flag> <portrait> <text file> [<counter><op><value>]`,
+// and the game ends on the first ending whose flag is set and whose counter
+// test, if any, holds. Tests are `<` or `>=`, e.g. `rep_helionix<0`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::quest::Quest;
+
+pub const ENDINGS_PATH: &str = "assets/texts/endings.txt";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Choice {
+    pub flag: String,
+    pub text: String,
+}
+
+impl Choice {
+    pub fn parse_all(text: &str) -> Vec<Choice> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (flag, text) = line.split_once(char::is_whitespace)?;
+                Some(Choice { flag: flag.to_string(), text: text.trim().to_string() })
+            })
+            .collect()
+    }
+
+    pub fn load_all<P: AsRef<Path>>(path: P) -> io::Result<Vec<Choice>> {
+        Ok(Choice::parse_all(&fs::read_to_string(path)?))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Ending {
+    pub flag: String,
+    pub portrait: String,
+    pub text_path: String,
+    pub requires: Option<Requirement>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Requirement {
+    pub counter: String,
+    pub at_least: bool,
+    pub value: i64,
+}
+
+impl Requirement {
+    pub fn parse(text: &str) -> Option<Requirement> {
+        let (counter, value, at_least) = match text.split_once(">=") {
+            Some((counter, value)) => (counter, value, true),
+            None => {
+                let (counter, value) = text.split_once('<')?;
+                (counter, value, false)
+            },
+        };
+
+        Some(Requirement {
+            counter: counter.to_string(),
+            at_least,
+            value: value.parse().ok()?,
+        })
+    }
+
+    pub fn holds(&self, quest: &Quest) -> bool {
+        (quest.count(&self.counter) >= self.value) == self.at_least
+    }
+}
+
+impl Ending {
+    pub fn parse_all(text: &str) -> Vec<Ending> {
+        text.lines()
+            .filter_map(|line| {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                let requires = match parts[..] {
+                    [flag, _, _] if !flag.starts_with('#') => None,
+                    [flag, _, _, requires] if !flag.starts_with('#') => Some(Requirement::parse(requires)?),
+                    _ => return None,
+                };
+
+                Some(Ending {
+                    flag: parts[0].to_string(),
+                    portrait: parts[1].to_string(),
+                    text_path: parts[2].to_string(),
+                    requires,
+                })
+            })
+            .collect()
+    }
+
+    pub fn load_all<P: AsRef<Path>>(path: P) -> io::Result<Vec<Ending>> {
+        Ok(Ending::parse_all(&fs::read_to_string(path)?))
+    }
+}
+
+pub fn ending_for<'a>(endings: &'a [Ending], quest: &Quest) -> Option<&'a Ending> {
+    endings.iter().find(|ending| {
+        quest.has(&ending.flag) && ending.requires.iter().all(|requires| requires.holds(quest))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_first_ending_set_wins() {
+        let endings = Ending::parse_all("# flag portrait text\nending_a a.png a.txt score<0\nending_a a.png c.txt\nending_b b.png b.txt\n");
+        let mut quest = Quest::new();
+        assert_eq!(ending_for(&endings, &quest), None);
+
+        quest.set("ending_b");
+        quest.set("ending_a");
+        assert_eq!(ending_for(&endings, &quest).map(|ending| ending.text_path.as_str()), Some("c.txt"));
+        quest.add("score", -1);
+        assert_eq!(ending_for(&endings, &quest).map(|ending| ending.text_path.as_str()), Some("a.txt"));
+
+        let choices = Choice::parse_all("take  Take the key.\n\n# comment\nrefuse Walk away.");
+        assert_eq!(choices[0], Choice { flag: String::from("take"), text: String::from("Take the key.") });
+        assert_eq!(choices.len(), 2);
+    }
+
+    #[test]
+    fn every_ending_has_its_files() {
+        let endings = Ending::load_all(ENDINGS_PATH).unwrap();
+        assert!(endings.len() > 1);
+
+        for ending in endings {
+            assert!(Path::new(&ending.text_path).exists(), "{} is missing", ending.text_path);
+            assert!(Path::new("assets").join(&ending.portrait).exists(), "{} is missing", ending.portrait);
+        }
+    }
+}